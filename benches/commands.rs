@@ -0,0 +1,97 @@
+//! Throughput of the commands on generated documents, and the memory they allocate.
+//!
+//! Every command runs on a wide and a deep document (see `generate`) of `JED_BENCH_RECORDS`
+//! records (20 000 by default, about 3 MB). Set it to a few millions to measure documents of
+//! hundreds of MB:
+//!
+//! ```bash
+//! JED_BENCH_RECORDS=2000000 cargo bench
+//! ```
+//!
+//! To evaluate a change, save the numbers of the code before it and compare against them:
+//!
+//! ```bash
+//! git stash && cargo bench -- --save-baseline before && git stash pop
+//! cargo bench -- --baseline before
+//! ```
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use jed::Program;
+use serde_json::Value;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[allow(dead_code)]
+mod generate;
+use generate::Shape;
+
+/// Counts the bytes allocated, to compare how much each command copies.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn records() -> usize {
+    std::env::var("JED_BENCH_RECORDS")
+        .ok()
+        .and_then(|records| records.parse().ok())
+        .unwrap_or(20_000)
+}
+
+fn bench(c: &mut Criterion, shape: Shape, name: &str, doc: &Value, script: &str) {
+    let program = Program::compile(script).unwrap();
+    let command = |v| program.apply(v).unwrap();
+    // The copy the command takes isn't counted, only what the command allocates itself
+    let input = doc.clone();
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    std::hint::black_box(command(input));
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - before;
+    eprintln!(
+        "{}/{name}: {:.1} MB allocated",
+        shape.name(),
+        allocated as f64 / 1_000_000.0
+    );
+    c.benchmark_group(shape.name()).bench_function(name, |b| {
+        b.iter_batched(|| doc.clone(), command, BatchSize::LargeInput)
+    });
+}
+
+fn commands(c: &mut Criterion) {
+    // Each script runs a single command, without an address for `substitute_values`
+    let scripts = [
+        ("substitute_values", "s/old/new/"),
+        (
+            "substitute_values_on_specified_ranges",
+            "/email/ s/old/new/",
+        ),
+        ("substitute_keys_on_specified_ranges", "/data/ S/name/new/"),
+        ("print_on_specified_ranges", "/author/./url/ p"),
+        ("delete_on_specified_ranges", "/meta/ d"),
+    ];
+    for shape in [Shape::Wide, Shape::Deep] {
+        let doc = generate::document(shape, records(), 0);
+        for (name, script) in scripts {
+            bench(c, shape, name, &doc, script);
+        }
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = commands
+}
+criterion_main!(benches);
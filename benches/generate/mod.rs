@@ -0,0 +1,107 @@
+//! Synthetic documents for the benchmarks. The same seed and size always give the same document,
+//! so two runs (or two branches) measure the same input.
+
+use serde_json::{json, Map, Value};
+
+/// The shapes of generated documents.
+#[derive(Clone, Copy)]
+pub enum Shape {
+    /// A long Array of small records, like an API dump.
+    Wide,
+    /// Records nested in each other, `DEPTH` levels deep.
+    Deep,
+}
+
+/// How many levels the records of a deep document are nested.
+pub const DEPTH: usize = 40;
+
+impl Shape {
+    pub fn name(self) -> &'static str {
+        match self {
+            Shape::Wide => "wide",
+            Shape::Deep => "deep",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Shape> {
+        match name {
+            "wide" => Some(Shape::Wide),
+            "deep" => Some(Shape::Deep),
+            _ => None,
+        }
+    }
+}
+
+/// A small pseudo random generator (SplitMix64), enough to vary the values without a dependency.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    pub fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+        choices[self.below(choices.len())]
+    }
+}
+
+/// A document of the given shape with `records` records in total, under a `data` key:
+/// `{"data": [...]}`. Every record has an `email`, an `author` with a `url`, and a `meta`.
+pub fn document(shape: Shape, records: usize, seed: u64) -> Value {
+    let mut rng = Rng::new(seed);
+    let data: Vec<Value> = match shape {
+        Shape::Wide => (0..records).map(|i| record(&mut rng, i)).collect(),
+        Shape::Deep => (0..records.div_ceil(DEPTH))
+            .map(|chain| {
+                let first = chain * DEPTH;
+                let last = records.min(first + DEPTH);
+                (first..last).rev().fold(Value::Null, |child, i| {
+                    let mut record = record(&mut rng, i);
+                    record["child"] = child;
+                    record
+                })
+            })
+            .collect(),
+    };
+    json!({ "data": data })
+}
+
+fn record(rng: &mut Rng, i: usize) -> Value {
+    let domain = rng.pick(&["old.com", "example.com", "mail.org"]);
+    let tags: Vec<Value> = (0..rng.below(4))
+        .map(|_| Value::from(rng.pick(&["admin", "beta", "staff", "old"])))
+        .collect();
+    let mut meta = Map::new();
+    meta.insert(
+        String::from("score"),
+        json!(rng.below(10_000) as f64 / 100.0),
+    );
+    meta.insert(String::from("active"), json!(rng.below(2) == 0));
+    if rng.below(3) == 0 {
+        meta.insert(String::from("parent"), Value::Null);
+    }
+    json!({
+        "id": i,
+        "name": format!("user{i}"),
+        "email": format!("user{i}@{domain}"),
+        "tags": tags,
+        "author": {
+            "name": format!("author{}", rng.below(100)),
+            "url": format!("https://{domain}/{}", rng.below(1000)),
+        },
+        "meta": meta,
+    })
+}
@@ -0,0 +1,34 @@
+//! Prints one of the documents the benchmarks run on, to time the jed binary on it:
+//!
+//! ```bash
+//! cargo run --release --example generate -- wide 1000000 > wide.json
+//! time jed -e '/email/ s/old.com/new.com/' wide.json > /dev/null
+//! ```
+//!
+//! Arguments: the shape (`wide` or `deep`), the number of records (20 000 by default) and the
+//! seed (0 by default).
+
+#[allow(dead_code)]
+#[path = "../benches/generate/mod.rs"]
+mod generate;
+
+use std::io::Write;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let shape = args.first().map_or("wide", String::as_str);
+    let Some(shape) = generate::Shape::from_name(shape) else {
+        eprintln!("Error: the shape is wide or deep, not {shape:?}");
+        std::process::exit(2);
+    };
+    let records = args.get(1).map_or(Ok(20_000), |records| records.parse());
+    let seed = args.get(2).map_or(Ok(0), |seed| seed.parse());
+    let (Ok(records), Ok(seed)) = (records, seed) else {
+        eprintln!("Error: the number of records and the seed are numbers");
+        std::process::exit(2);
+    };
+    let document = generate::document(shape, records, seed);
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+    serde_json::to_writer(&mut stdout, &document).unwrap();
+    writeln!(stdout).unwrap();
+}
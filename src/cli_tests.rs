@@ -0,0 +1,687 @@
+use super::*;
+use jed::Program;
+
+#[test]
+fn test_cst_render_keeps_unchanged_text() {
+    let source = "{\n  \"a\" :  1.50,\n  \"b\": [1,2,  3],\n  \"c\": {\"d\": \"x\"}\n}\n";
+    let root = cst::parse(source, false).unwrap();
+    let v = cst::to_value(source, &root).unwrap();
+    assert_eq!(v["b"][2], 3);
+    assert_eq!(cst::render(source, &root, &v).unwrap(), source);
+
+    let edited = Program::compile("s/x/y/")
+        .unwrap()
+        .apply(v.clone())
+        .unwrap();
+    assert_eq!(
+        cst::render(source, &root, &edited).unwrap(),
+        source.replace("\"x\"", "\"y\"")
+    );
+    let renamed = Program::compile("S/^a$/z/")
+        .unwrap()
+        .apply(v.clone())
+        .unwrap();
+    assert_eq!(
+        cst::render(source, &root, &renamed).unwrap(),
+        source.replace("\"a\"", "\"z\"")
+    );
+    let deleted = Program::compile("/b/.1,1 d")
+        .unwrap()
+        .apply(v.clone())
+        .unwrap();
+    assert_eq!(
+        cst::render(source, &root, &deleted).unwrap(),
+        source.replace("[1,2,  3]", "[1,  3]")
+    );
+}
+#[test]
+fn test_cst_render_indents_new_values() {
+    let source = "{\n    \"a\": 1\n}";
+    let root = cst::parse(source, false).unwrap();
+    let v = serde_json::from_str(r#"{"a": 1, "b": {"c": [true]}}"#).unwrap();
+    assert_eq!(
+        cst::render(source, &root, &v).unwrap(),
+        "{\n    \"a\": 1,\n    \"b\": {\n        \"c\": [\n            true\n        ]\n    }\n}"
+    );
+    let compact = r#"{"a":1}"#;
+    let root = cst::parse(compact, false).unwrap();
+    assert_eq!(
+        cst::render(compact, &root, &v).unwrap(),
+        r#"{"a":1,"b":{"c":[true]}}"#
+    );
+}
+#[test]
+fn test_cst_parse_errors() {
+    assert!(cst::parse(r#"{"a": 1,}"#, false).is_err());
+    assert!(cst::parse(r#"{"a": tru}"#, false).is_err());
+    assert!(cst::parse(r#"["a"] x"#, false).is_err());
+    assert!(cst::parse(r#"[""#, false).is_err());
+}
+#[test]
+fn test_output_options() {
+    let v: Value = serde_json::from_str(r#"{"b": {"z": 1, "a": "é"}, "a": [1, 2]}"#).unwrap();
+    let compact = output::OutputOptions {
+        compact: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        output::to_text(&v, &compact).unwrap(),
+        r#"{"b":{"z":1,"a":"é"},"a":[1,2]}"#
+    );
+    let sorted_ascii = output::OutputOptions {
+        compact: true,
+        sort_keys: true,
+        ascii: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        output::to_text(&v, &sorted_ascii).unwrap(),
+        r#"{"a":[1,2],"b":{"a":"\u00e9","z":1}}"#
+    );
+    let tabs = output::OutputOptions {
+        indent: String::from("\t"),
+        ..Default::default()
+    };
+    assert_eq!(
+        output::to_text(&serde_json::json!({"a": [1]}), &tabs).unwrap(),
+        "{\n\t\"a\": [\n\t\t1\n\t]\n}"
+    );
+}
+#[test]
+fn test_raw_output() {
+    let v: Value = serde_json::from_str(
+        r#"{"a": {"name": "x y", "n": 3, "empty": []}, "b": [{"name": "z"}, null]}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        output::to_raw_text(&v, false).unwrap(),
+        "x y\n3\n[]\nz\nnull\n"
+    );
+    assert_eq!(output::to_raw_text(&v, true).unwrap(), "x y3[]znull");
+    assert_eq!(
+        output::to_raw_text(&Value::String(String::from("\"q\"")), false).unwrap(),
+        "\"q\"\n"
+    );
+}
+#[test]
+fn test_flatten() {
+    let v: Value =
+        serde_json::from_str(r#"{"a": {"na me": "x\"]y", "n": 3.10}, "b": [{"name": "z"}, []]}"#)
+            .unwrap();
+    let flattened = flatten::flatten(&v).unwrap();
+    assert_eq!(
+        flattened,
+        r#"json = {};
+json.a = {};
+json.a["na me"] = "x\"]y";
+json.a.n = 3.10;
+json.b = [];
+json.b[0] = {};
+json.b[0].name = "z";
+json.b[1] = [];
+"#
+    );
+    assert_eq!(flatten::unflatten(&flattened).unwrap(), v);
+    let reversed: String = flattened.lines().rev().map(|l| format!("{l}\n")).collect();
+    assert_eq!(flatten::unflatten(&reversed).unwrap(), v);
+    assert_eq!(
+        flatten::unflatten("json.b[1].name = \"z\";").unwrap(),
+        serde_json::json!({"b": [null, {"name": "z"}]})
+    );
+    assert!(flatten::unflatten("json.a = ;").is_err());
+    assert!(flatten::unflatten("other.a = 1;").is_err());
+    let err = flatten::unflatten("json = [];\njson[18446744073709551615] = 1;").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Could not parse line 2 of the flattened input"
+    );
+    let err = flatten::unflatten("json.a[4000000000] = 1;").unwrap_err();
+    assert!(format!("{err:#}").contains("Index 4000000000 is too far past the end"));
+    assert_eq!(
+        flatten::unflatten("json[3] = 1;").unwrap(),
+        serde_json::json!([null, null, null, 1])
+    );
+}
+#[test]
+fn test_diff() {
+    use diff::{Change, PathSegment};
+    let old: Value = serde_json::from_str(
+        r#"{"a": 1, "b": {"c": [1, 2, 3, 4], "d/e~": true}, "f": [1], "g": null}"#,
+    )
+    .unwrap();
+    let new: Value =
+        serde_json::from_str(r#"{"a": 2, "b": {"c": [1, 3], "d/e~": true}, "f": [1, {}], "h": 0}"#)
+            .unwrap();
+    let changes = diff::diff(&old, &new);
+    assert_eq!(
+        changes,
+        vec![
+            Change::Replace(vec![PathSegment::Key(String::from("a"))], Value::from(2)),
+            Change::Remove(vec![
+                PathSegment::Key(String::from("b")),
+                PathSegment::Key(String::from("c")),
+                PathSegment::Index(3)
+            ]),
+            Change::Remove(vec![
+                PathSegment::Key(String::from("b")),
+                PathSegment::Key(String::from("c")),
+                PathSegment::Index(1)
+            ]),
+            Change::Add(
+                vec![PathSegment::Key(String::from("f")), PathSegment::Index(1)],
+                serde_json::json!({})
+            ),
+            Change::Remove(vec![PathSegment::Key(String::from("g"))]),
+            Change::Add(vec![PathSegment::Key(String::from("h"))], Value::from(0)),
+        ]
+    );
+    assert_eq!(
+        diff::to_pointer(&[
+            PathSegment::Key(String::from("d/e~")),
+            PathSegment::Index(0)
+        ]),
+        "/d~1e~0/0"
+    );
+    assert_eq!(
+        diff::to_json_patch(&diff::diff(&old, &Value::Null)),
+        serde_json::json!([{"op": "replace", "path": "", "value": null}])
+    );
+    assert!(diff::diff(&old, &old).is_empty());
+}
+#[test]
+fn test_apply_json_patch() {
+    let v = serde_json::json!({"a": {"b": [1, 2]}, "c/d": "x", "e": 1});
+    let json_patch = serde_json::json!([
+        {"op": "add", "path": "/a/b/1", "value": 9},
+        {"op": "add", "path": "/a/b/-", "value": 3},
+        {"op": "remove", "path": "/c~1d"},
+        {"op": "replace", "path": "/e", "value": {"f": true}},
+        {"op": "copy", "from": "/e", "path": "/g"},
+        {"op": "move", "from": "/a/b", "path": "/b"},
+        {"op": "test", "path": "/g/f", "value": true}
+    ]);
+    let patched = patch::apply_json_patch(v.clone(), &json_patch).unwrap();
+    assert_eq!(
+        patched,
+        serde_json::json!({"a": {}, "e": {"f": true}, "g": {"f": true}, "b": [1, 9, 2, 3]})
+    );
+    let keys: Vec<&String> = patched.as_object().unwrap().keys().collect();
+    assert_eq!(keys, ["a", "e", "g", "b"]);
+    for failing in [
+        serde_json::json!([{"op": "test", "path": "/e", "value": 2}]),
+        serde_json::json!([{"op": "remove", "path": "/missing"}]),
+        serde_json::json!([{"op": "add", "path": "/a/b/5", "value": 0}]),
+        serde_json::json!([{"op": "move", "from": "/a", "path": "/a/b/c"}]),
+        serde_json::json!([{"op": "unknown", "path": "/a"}]),
+        serde_json::json!({"op": "remove", "path": "/a"}),
+    ] {
+        assert!(patch::apply_json_patch(v.clone(), &failing).is_err());
+    }
+    // Numbers are tested by their value, not by their text
+    let numbers: Value =
+        serde_json::from_str(r#"{"a": 1.0, "b": [-150, 0.5], "c": {"d": 0}}"#).unwrap();
+    let test: Value = serde_json::from_str(
+        r#"[{"op": "test", "path": "/a", "value": 1},
+            {"op": "test", "path": "/b", "value": [-1.5e2, 5E-1]},
+            {"op": "test", "path": "/c", "value": {"d": -0.0}}]"#,
+    )
+    .unwrap();
+    assert!(patch::apply_json_patch(numbers.clone(), &test).is_ok());
+    let test: Value =
+        serde_json::from_str(r#"[{"op": "test", "path": "/a", "value": 1.01}]"#).unwrap();
+    assert!(patch::apply_json_patch(numbers, &test).is_err());
+    // The patch emitted for an edit applies back to the input
+    let edited = serde_json::json!({"a": {"b": [2]}, "c/d": "y", "h": null});
+    let emitted = diff::to_json_patch(&diff::diff(&v, &edited));
+    assert_eq!(patch::apply_json_patch(v, &emitted).unwrap(), edited);
+}
+#[test]
+fn test_apply_merge_patch() {
+    let mut v = serde_json::json!({"title": "Goodbye!", "author": {"givenName": "John", "familyName": "Doe"}, "tags": ["example", "sample"], "content": "This will be unchanged"});
+    patch::apply_merge_patch(
+        &mut v,
+        &serde_json::json!({"title": "Hello!", "phoneNumber": "+01-123-456-7890", "author": {"familyName": null}, "tags": ["example"]}),
+    );
+    assert_eq!(
+        v,
+        serde_json::json!({"title": "Hello!", "author": {"givenName": "John"}, "tags": ["example"], "content": "This will be unchanged", "phoneNumber": "+01-123-456-7890"})
+    );
+    patch::apply_merge_patch(&mut v, &serde_json::json!([1]));
+    assert_eq!(v, serde_json::json!([1]));
+}
+#[test]
+fn test_diff_formats() {
+    let old = serde_json::json!({"version": "1.0.0", "deps": ["a", "b"]});
+    let new = serde_json::json!({"version": "1.1.0", "deps": ["b"], "new": true});
+    assert_eq!(
+        diff::paths(&old, &new),
+        "~ /version: \"1.0.0\" -> \"1.1.0\"\n- /deps/0: \"a\"\n+ /new: true\n"
+    );
+    assert_eq!(
+        diff::unified("a\nb\nc\n", "a\nB\nc\n", "file.json"),
+        "--- a/file.json\n+++ b/file.json\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+    );
+    assert_eq!(diff::unified("a\n", "a\n", "file.json"), "");
+}
+#[test]
+fn test_yaml_documents() {
+    let yaml = "name: api\nports: [80, 443]\nratio: 1.5\n---\nname: web\n";
+    let documents = format::parse_documents(yaml, format::Format::Yaml, false).unwrap();
+    assert_eq!(
+        documents,
+        vec![
+            serde_json::json!({"name": "api", "ports": [80, 443], "ratio": 1.5}),
+            serde_json::json!({"name": "web"})
+        ]
+    );
+    assert_eq!(
+        format::to_text(
+            &documents[0],
+            format::Format::Yaml,
+            &[],
+            &Default::default()
+        )
+        .unwrap(),
+        "name: api\nports:\n- 80\n- 443\nratio: 1.5\n"
+    );
+    let big: Value = serde_json::from_str(r#"{"big": 18446744073709551615, "neg": -3}"#).unwrap();
+    assert_eq!(
+        format::to_text(&big, format::Format::Yaml, &[], &Default::default()).unwrap(),
+        "big: 18446744073709551615\nneg: -3\n"
+    );
+    assert!(format::parse_documents("a: [", format::Format::Yaml, false).is_err());
+    assert!(format::Format::from_extension("deploy/Service.YML") == Some(format::Format::Yaml));
+    assert!(format::Format::from_extension("Makefile").is_none());
+}
+#[test]
+fn test_toml_documents() {
+    let toml = "[package]\nname = \"demo\"\nreleased = 1979-05-27T07:32:00Z\nratio = 0.5\n\n[[bin]]\nname = \"x\"\n";
+    let documents = format::parse_documents(toml, format::Format::Toml, false).unwrap();
+    assert_eq!(
+        documents,
+        vec![serde_json::json!({
+            "package": {"name": "demo", "released": "1979-05-27T07:32:00Z", "ratio": 0.5},
+            "bin": [{"name": "x"}]
+        })]
+    );
+    let datetimes = format::toml_datetimes(toml).unwrap();
+    assert_eq!(
+        format::to_text(&documents[0], format::Format::Toml, &[], &datetimes).unwrap(),
+        toml
+    );
+    // Only the Strings that were dates and times in the input go back to dates and times
+    let v: Value =
+        serde_json::from_str(r#"{"a": "1979-05-27", "b": 18446744073709551615}"#).unwrap();
+    let error = format::to_text(&v, format::Format::Toml, &[], &datetimes).unwrap_err();
+    assert!(error.to_string().contains("18446744073709551615 at \"/b\""));
+    assert_eq!(
+        format::to_text(
+            &serde_json::json!({"a": "1979-05-27", "b": 1e30}),
+            format::Format::Toml,
+            &[],
+            &datetimes
+        )
+        .unwrap(),
+        "a = \"1979-05-27\"\nb = 1000000000000000000000000000000.0\n"
+    );
+    let error = format::to_text(
+        &serde_json::json!({"a": {"b": [1, null]}}),
+        format::Format::Toml,
+        &[],
+        &Default::default(),
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("/a/b/1"));
+    assert!(format::to_text(
+        &serde_json::json!([1]),
+        format::Format::Toml,
+        &[],
+        &Default::default()
+    )
+    .is_err());
+    assert!(format::parse_documents("a = ", format::Format::Toml, false).is_err());
+}
+#[test]
+fn test_cst_jsonc() {
+    let source = "// settings\n{\n  /* editor */\n  \"tab\": 2, // spaces\n  \"wrap\": true,\n  \"list\": [1, 2,],\n}\n";
+    assert!(cst::parse(source, false).is_err());
+    let root = cst::parse(source, true).unwrap();
+    let v = cst::to_value(source, &root).unwrap();
+    assert_eq!(
+        v,
+        serde_json::json!({"tab": 2, "wrap": true, "list": [1, 2]})
+    );
+    assert_eq!(cst::render(source, &root, &v).unwrap(), source);
+
+    let edited = Program::compile("s/^2$/4/")
+        .unwrap()
+        .apply(v.clone())
+        .unwrap();
+    assert_eq!(
+        cst::render(source, &root, &edited).unwrap(),
+        source
+            .replace("2, // spaces", "4, // spaces")
+            .replace("[1, 2,]", "[1, 4,]")
+    );
+    let deleted = serde_json::json!({"tab": 2});
+    assert_eq!(
+        cst::render(source, &root, &deleted).unwrap(),
+        "// settings\n{\n  /* editor */\n  \"tab\": 2 // spaces\n}\n"
+    );
+    assert!(cst::parse("{\"a\": 1 /* open", true).is_err());
+}
+#[test]
+fn test_json5_documents() {
+    let documents = format::parse_documents(
+        "{a: 'x', b: 0x10, c: [1,], // c\n}",
+        format::Format::Json5,
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        documents,
+        vec![serde_json::json!({"a": "x", "b": 16, "c": [1]})]
+    );
+    assert!(format::parse_documents("{a: }", format::Format::Json5, false).is_err());
+}
+#[test]
+fn test_csv_output() {
+    let v = serde_json::json!({"data": [
+        {"name": "a, b", "id": 1, "tags": ["x"]},
+        {"id": 2, "name": "say \"hi\"", "extra": null}
+    ]});
+    assert_eq!(
+        format::to_text(&v, format::Format::Csv, &[], &Default::default()).unwrap(),
+        "name,id,tags,extra\n\"a, b\",1,\"[\"\"x\"\"]\",\n\"say \"\"hi\"\"\",2,,\n"
+    );
+    let columns = vec![String::from("id"), String::from("name")];
+    assert_eq!(
+        format::to_text(&v, format::Format::Tsv, &columns, &Default::default()).unwrap(),
+        "id\tname\n1\ta, b\n2\t\"say \"\"hi\"\"\"\n"
+    );
+    assert_eq!(
+        format::to_text(
+            &serde_json::json!({"a": 1, "b": true}),
+            format::Format::Csv,
+            &[],
+            &Default::default()
+        )
+        .unwrap(),
+        "a,b\n1,true\n"
+    );
+    assert!(format::to_text(
+        &serde_json::json!([{"a": 1}, 2]),
+        format::Format::Csv,
+        &[],
+        &Default::default()
+    )
+    .is_err());
+}
+#[test]
+fn test_csv_input() {
+    let csv = "id,zip,name,ok,note\n1,01234,\"a, b\",true,\n2.5,9,c,false,x\n";
+    assert_eq!(
+        format::parse_documents(csv, format::Format::Csv, false).unwrap(),
+        vec![serde_json::json!([
+            {"id": "1", "zip": "01234", "name": "a, b", "ok": "true", "note": ""},
+            {"id": "2.5", "zip": "9", "name": "c", "ok": "false", "note": "x"}
+        ])]
+    );
+    assert_eq!(
+        format::parse_documents(csv, format::Format::Csv, true).unwrap(),
+        vec![serde_json::json!([
+            {"id": 1, "zip": "01234", "name": "a, b", "ok": true, "note": null},
+            {"id": 2.5, "zip": 9, "name": "c", "ok": false, "note": "x"}
+        ])]
+    );
+    assert_eq!(
+        format::parse_documents("a\tb\n1\t2\n", format::Format::Tsv, false).unwrap(),
+        vec![serde_json::json!([{"a": "1", "b": "2"}])]
+    );
+    assert!(format::parse_documents("a,b\n1\n", format::Format::Csv, false).is_err());
+}
+#[test]
+fn test_binary_documents() {
+    let v =
+        serde_json::json!({"a": [1, -2, 2.5, 18446744073709551615u64], "b": {"c": null}, "d": "é"});
+    for format in [format::Format::Msgpack, format::Format::Cbor] {
+        let bytes = format::to_bytes(&v, format, &HashMap::new()).unwrap();
+        assert_eq!(
+            format::parse_binary_documents(&bytes, format).unwrap(),
+            vec![v.clone()]
+        );
+        let two = [bytes.clone(), bytes].concat();
+        assert_eq!(
+            format::parse_binary_documents(&two, format).unwrap(),
+            vec![v.clone(), v.clone()]
+        );
+        assert!(format::parse_binary_documents(&[], format).is_err());
+    }
+    // Binary data is read as base64
+    let msgpack = b"\x82\xa1a\xc4\x03abc\x01\xd4\x05\x2a";
+    assert_eq!(
+        format::parse_binary_documents(msgpack, format::Format::Msgpack).unwrap(),
+        vec![serde_json::json!({"a": "YWJj", "1": "Kg=="})]
+    );
+    let cbor = b"\xa2\x61a\x43abc\x01\xc0\x6510:00";
+    assert_eq!(
+        format::parse_binary_documents(cbor, format::Format::Cbor).unwrap(),
+        vec![serde_json::json!({"a": "YWJj", "1": "10:00"})]
+    );
+    // and written back as binary data where the input had it
+    let msgpack = b"\x83\xa1a\xc4\x03abc\xa1e\xd4\x05\x2a\xa1s\xa4YWJj";
+    let cbor = b"\xa2\x61a\x43abc\x61s\x64YWJj";
+    for (bytes, format) in [
+        (&msgpack[..], format::Format::Msgpack),
+        (&cbor[..], format::Format::Cbor),
+    ] {
+        let binary = format::binary_paths(bytes, format).unwrap();
+        assert_eq!(binary.get("/a"), Some(&format::Binary::Bytes));
+        assert!(!binary.contains_key("/s"));
+        let v = format::parse_binary_documents(bytes, format)
+            .unwrap()
+            .remove(0);
+        assert_eq!(format::to_bytes(&v, format, &binary).unwrap(), bytes);
+    }
+    assert_eq!(
+        format::binary_paths(msgpack, format::Format::Msgpack).unwrap()["/e"],
+        format::Binary::Ext(5)
+    );
+}
+#[test]
+fn test_stream_reader() {
+    let mut reader = stream::Reader::new(r#"{"a": [1.10, "\u00e9", true], "b": {}}"#.as_bytes());
+    let mut events = Vec::new();
+    while let Some(event) = reader.next_event().unwrap() {
+        events.push(event);
+    }
+    assert_eq!(
+        events,
+        vec![
+            stream::Event::StartObject,
+            stream::Event::Key(String::from("a")),
+            stream::Event::StartArray,
+            stream::Event::Scalar(serde_json::from_str("1.10").unwrap()),
+            stream::Event::Scalar(Value::String(String::from("é"))),
+            stream::Event::Scalar(Value::Bool(true)),
+            stream::Event::EndArray,
+            stream::Event::Key(String::from("b")),
+            stream::Event::StartObject,
+            stream::Event::EndObject,
+            stream::Event::EndObject,
+        ]
+    );
+    for invalid in ["{\"a\" 1}", "[1,]", "[1] 2", "[1", "tru", "01", "{1: 2}"] {
+        let mut reader = stream::Reader::new(invalid.as_bytes());
+        let mut result = Ok(None);
+        for _ in 0..10 {
+            result = reader.next_event();
+            if !matches!(result, Ok(Some(_))) {
+                break;
+            }
+        }
+        assert!(result.is_err(), "{invalid}");
+    }
+}
+#[test]
+fn test_stream_commands() {
+    let input = r#"{"commit": {"author": {"name": "old", "mail": "old@x"}}, "list": [1, {"name": "old"}, null, {}]}"#;
+    let run = |expression: &str| {
+        let program = Program::compile(expression).unwrap();
+        let (stack, command) = program.commands().next().unwrap();
+        let mut output = Vec::new();
+        let options = output::OutputOptions {
+            compact: true,
+            ..Default::default()
+        };
+        let matches = stream::run(input.as_bytes(), &mut output, stack, command, &options).unwrap();
+        (
+            String::from_utf8(output).unwrap().trim_end().to_string(),
+            matches,
+        )
+    };
+    assert_eq!(
+        run("/commit/./author/./name/ s/old/new/g"),
+        (
+            String::from(
+                r#"{"commit":{"author":{"name":"new","mail":"old@x"}},"list":[1,{"name":"old"}]}"#
+            ),
+            1
+        )
+    );
+    assert_eq!(
+        run("/name/ p"),
+        (
+            String::from(r#"{"commit":{"author":{"name":"old"}},"list":[{"name":"old"}]}"#),
+            2
+        )
+    );
+    assert_eq!(
+        run("/list/.1,3 d"),
+        (
+            String::from(r#"{"commit":{"author":{"name":"old","mail":"old@x"}},"list":[1]}"#),
+            3
+        )
+    );
+    assert_eq!(
+        run("/commit/./author/ S/^/_/"),
+        (
+            String::from(
+                r#"{"commit":{"_author":{"_name":"old","_mail":"old@x"}},"list":[1,{"name":"old"}]}"#
+            ),
+            3
+        )
+    );
+    assert_eq!(run(":/^x$/ p"), (String::from("null"), 0));
+    let program = Program::compile("/a/ h").unwrap();
+    let (stack, command) = program.commands().next().unwrap();
+    let options = output::OutputOptions::default();
+    assert!(stream::run("{}".as_bytes(), Vec::new(), stack, command, &options).is_err());
+}
+#[test]
+fn test_stream_matches_tree_engine() {
+    let documents = [
+        r#"{"a": [1, 2], "b": {"a": {"k": 1}}}"#,
+        r#"{"a": {"b": "xx"}, "c": "xx"}"#,
+        r#"{"a": {"x": {"a": {"b": 1}}}}"#,
+        r#"{"a": "x", "b": ["x", "y", null, [], {}], "c": 1, "d": null, "e": {}}"#,
+        r#"[{"a": {"b": "x"}}, {"b": [1, "x"]}, [], null, "x"]"#,
+        r#"{"k": [{"k": {"a": "x"}}, {"b": null}], "a": [[1, "x"], {"a": null}], "b": {"b": {"b": "xb"}}}"#,
+        r#"{"a": {"b": {}, "c": []}, "b": [[], {}]}"#,
+        r#""x""#,
+        "[]",
+    ];
+    let expressions = [
+        "p",
+        "d",
+        "s/x/y/",
+        "S/a/A/",
+        "/a/ p",
+        "/a/ d",
+        "/a/ s/x/y/",
+        "/b/ s/x/y/",
+        "/a/./b/ p",
+        "/a/./b/ d",
+        "/a/./b/ s/x/y/",
+        "/a/./b/ S/b/B/",
+        ":/x/ p",
+        ":/x/ d",
+        ":/x/ s/x/y/",
+        "/a/.0,0 p",
+        "/a/.0,0 d",
+        "0,1 d",
+        "0,0./a/ p",
+        "/b/.0,1 s/x/y/",
+        "/k/./k/./a/ p",
+        "/b/./b/ S/b/c/",
+    ];
+    let options = output::OutputOptions {
+        compact: true,
+        ..Default::default()
+    };
+    for expression in expressions {
+        let program = Program::compile(expression).unwrap();
+        let (stack, command) = program.commands().next().unwrap();
+        for document in documents {
+            let expected = program.run(serde_json::from_str(document).unwrap()).unwrap();
+            let mut output = Vec::new();
+            let matches =
+                stream::run(document.as_bytes(), &mut output, stack, command, &options).unwrap();
+            let v: Value = serde_json::from_slice(&output).unwrap();
+            assert_eq!(
+                (v, matches),
+                (expected.v, expected.matches),
+                "{expression} on {document}"
+            );
+        }
+    }
+}
+#[test]
+fn test_stream_pretty() {
+    let input = r#"{"a": {"b": [1, {}], "c": []}, "d": "é"}"#;
+    let program = Program::compile("p").unwrap();
+    let (stack, command) = program.commands().next().unwrap();
+    let mut output = Vec::new();
+    stream::run(
+        input.as_bytes(),
+        &mut output,
+        stack,
+        command,
+        &output::OutputOptions::default(),
+    )
+    .unwrap();
+    let v: Value = serde_json::from_str(input).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        serde_json::to_string_pretty(&v).unwrap() + "\n"
+    );
+}
+#[test]
+fn test_parallel_for_each() {
+    for jobs in [1, 4] {
+        let mut results = Vec::new();
+        let consume = |r| {
+            results.push(r);
+            Ok(true)
+        };
+        parallel_for_each(jobs, 0..100, |i| i * 2, consume).unwrap();
+        assert_eq!(results, (0..100).map(|i| i * 2).collect::<Vec<_>>());
+
+        let mut results = Vec::new();
+        let consume = |r| {
+            results.push(r);
+            Ok(r != 10)
+        };
+        parallel_for_each(jobs, 0..100, |i| i, consume).unwrap();
+        assert_eq!(results, (0..=10).collect::<Vec<_>>());
+
+        let consume = |r| {
+            if r == 3 {
+                anyhow::bail!("bad")
+            } else {
+                Ok(true)
+            }
+        };
+        assert!(parallel_for_each(jobs, 0..100, |i| i, consume).is_err());
+    }
+}
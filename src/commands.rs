@@ -1,288 +1,292 @@
 use crate::grammar::{ArrayRange, RangeType};
 use regex::Regex;
 use serde_json::{Map, Number, Value};
+use std::cell::{Cell, RefCell};
 
 /// Performs a substitution on the keys of the JSON recursively.
-pub fn substitute_keys(v: Value, replace_regex: &Regex, replace_with: &String) -> Value {
+/// Returns the new JSON and the number of keys that were substituted.
+pub fn substitute_keys(v: Value, replace_regex: &Regex, replace_with: &String) -> (Value, usize) {
     match v {
         Value::Object(old_map) => {
+            let mut replaced = 0;
             let mut new_map: Map<String, Value> = Map::new();
             for (k, v) in old_map {
+                if replace_regex.is_match(&k) {
+                    replaced += 1;
+                }
                 let new_key = replace_regex.replace_all(&k, replace_with).into_owned();
-                let new_v = substitute_keys(v, replace_regex, replace_with);
+                let (new_v, new_v_replaced) = substitute_keys(v, replace_regex, replace_with);
+                replaced += new_v_replaced;
                 new_map.insert(new_key, new_v);
             }
-            Value::Object(new_map)
+            (Value::Object(new_map), replaced)
         }
-        Value::String(v) => Value::String(v),
+        Value::String(v) => (Value::String(v), 0),
         Value::Array(v) => {
+            let mut replaced = 0;
             let mut new_vec = Vec::new();
             for value in v {
-                let new_v = substitute_keys(value, replace_regex, replace_with);
+                let (new_v, new_v_replaced) = substitute_keys(value, replace_regex, replace_with);
+                replaced += new_v_replaced;
                 new_vec.push(new_v);
             }
-            Value::Array(new_vec)
+            (Value::Array(new_vec), replaced)
         }
-        Value::Null => Value::Null,
-        Value::Bool(v) => Value::Bool(v),
-        Value::Number(v) => Value::Number(v),
+        Value::Null => (Value::Null, 0),
+        Value::Bool(v) => (Value::Bool(v), 0),
+        Value::Number(v) => (Value::Number(v), 0),
+    }
+}
+/// Reads the result of a substitution back as a number. Numbers keep their exact digits
+/// (`1.10` stays `1.10`, big integers aren't rounded), anything that isn't a valid JSON
+/// number (`NaN`, `inf`, `007`...) is rejected.
+fn parse_number(s: &str) -> Option<Number> {
+    if s.trim() != s {
+        return None;
     }
+    serde_json::from_str::<Number>(s).ok()
 }
-pub fn substitute_values(v: Value, search_regexp: &Regex, replace_with: &String) -> Value {
+
+/// Performs a substitution on the values of the JSON recursively.
+/// Returns the new JSON and the number of values that were substituted.
+pub fn substitute_values(v: Value, search_regexp: &Regex, replace_with: &String) -> (Value, usize) {
     match v {
-        Value::Object(old_map) => {
-            let mut new_map: Map<String, Value> = Map::new();
-            for (k, v) in old_map {
-                let new_v = substitute_values(v, search_regexp, replace_with);
-                new_map.insert(k, new_v);
+        Value::Object(mut map) => {
+            let mut replaced = 0;
+            for v in map.values_mut() {
+                let (new_v, new_v_replaced) =
+                    substitute_values(std::mem::take(v), search_regexp, replace_with);
+                replaced += new_v_replaced;
+                *v = new_v;
             }
-            Value::Object(new_map)
+            (Value::Object(map), replaced)
         }
-        Value::String(v) => Value::String(search_regexp.replace_all(&v, replace_with).into_owned()),
-        Value::Array(v) => {
-            let mut new_vec = Vec::new();
-            for value in v {
-                let new_v = substitute_values(value, search_regexp, replace_with);
-                new_vec.push(new_v);
+        Value::String(v) => {
+            if !search_regexp.is_match(&v) {
+                return (Value::String(v), 0);
             }
-            Value::Array(new_vec)
+            (
+                Value::String(search_regexp.replace_all(&v, replace_with).into_owned()),
+                1,
+            )
+        }
+        Value::Array(mut vec) => {
+            let mut replaced = 0;
+            for value in vec.iter_mut() {
+                let (new_v, new_v_replaced) =
+                    substitute_values(std::mem::take(value), search_regexp, replace_with);
+                replaced += new_v_replaced;
+                *value = new_v;
+            }
+            (Value::Array(vec), replaced)
         }
         Value::Null => {
             let old_null = "null".to_string();
+            if !search_regexp.is_match(&old_null) {
+                return (Value::Null, 0);
+            }
             let old_null_replaced = search_regexp
                 .replace_all(&old_null, replace_with)
                 .into_owned();
             if old_null == old_null_replaced {
-                return Value::Null;
+                return (Value::Null, 1);
             } else {
-                if let Ok(int) = &old_null_replaced.parse::<i128>() {
-                    return Value::Number(Number::from_i128(*int).unwrap());
-                }
-                if let Ok(float) = &old_null_replaced.parse::<f64>() {
-                    return Value::Number(Number::from_f64(*float).unwrap());
+                if let Some(number) = parse_number(&old_null_replaced) {
+                    return (Value::Number(number), 1);
                 }
                 if let Ok(new_bool) = &old_null_replaced.parse::<bool>() {
-                    return Value::Bool(*new_bool);
+                    return (Value::Bool(*new_bool), 1);
                 }
             }
-            Value::String(old_null_replaced)
+            (Value::String(old_null_replaced), 1)
         }
         Value::Bool(v) => {
             let old_bool = v.to_string();
+            if !search_regexp.is_match(&old_bool) {
+                return (Value::Bool(v), 0);
+            }
             let old_bool_replaced = search_regexp
                 .replace_all(&old_bool, replace_with)
                 .into_owned();
             if old_bool == old_bool_replaced {
-                return Value::Bool(v);
+                return (Value::Bool(v), 1);
             } else if let Ok(new_bool) = &old_bool_replaced.parse::<bool>() {
-                return Value::Bool(*new_bool);
+                return (Value::Bool(*new_bool), 1);
             }
-            Value::String(old_bool_replaced)
+            (Value::String(old_bool_replaced), 1)
         }
         Value::Number(v) => {
             let old_number = v.to_string();
+            if !search_regexp.is_match(&old_number) {
+                return (Value::Number(v), 0);
+            }
             let old_number_replaced = search_regexp
                 .replace_all(&old_number, replace_with)
                 .into_owned();
             if old_number == old_number_replaced {
-                return Value::Number(v);
+                return (Value::Number(v), 1);
             } else {
-                if let Ok(int) = &old_number_replaced.parse::<i128>() {
-                    return Value::Number(Number::from_i128(*int).unwrap());
-                }
-                if let Ok(float) = &old_number_replaced.parse::<f64>() {
-                    return Value::Number(Number::from_f64(*float).unwrap());
+                if let Some(number) = parse_number(&old_number_replaced) {
+                    return (Value::Number(number), 1);
                 }
             }
-            Value::String(old_number_replaced)
+            (Value::String(old_number_replaced), 1)
         }
     }
 }
 
-/// This function performs the substitution only in the values that match the filter "stack"
-pub fn print_on_specified_ranges(v: Value, stack: &[RangeType]) -> Value {
+/// Keeps only the nodes that match the filter "stack" (and their ancestors).
+/// Returns the pruned JSON and the number of matches.
+pub fn print_on_specified_ranges(v: Value, stack: &[RangeType]) -> (Value, usize) {
+    // `p` without an address keeps the whole document, which counts as a match for the exit
+    // status
+    if stack.is_empty() {
+        return (v, 1);
+    }
     fn operate_on_object(
         map: Map<String, Value>,
-        re: Regex,
+        re: &Regex,
         stack: &[RangeType],
         stack_anchored: bool,
+        matches: &Cell<usize>,
     ) -> Value {
         let mut new_map: Map<String, Value> = Map::new();
-        // It was already popped before
-        if stack_anchored {
-            for (k, v) in map {
-                if re.find(&k).is_some() {
-                    new_map.insert(k.clone(), v.clone());
-                }
-            }
-        } else {
-            for (k, v) in map {
-                if re.find(&k).is_some() {
-                    new_map.insert(k.clone(), v.clone());
-                } else {
-                    let new_v = apply_on_range(
-                        v.clone(),
-                        stack,
-                        false,
-                        false,
-                        &OperateOnCallbacks {
-                            operate_on_object: &operate_on_object,
-                            operate_on_array: &operate_on_array,
-                            operate_on_string: &operate_on_string,
+        for (k, v) in map {
+            if re.find(&k).is_some() {
+                matches.set(matches.get() + 1);
+                new_map.insert(k, v);
+            } else if !stack_anchored {
+                // An anchored stack was already popped before
+                let new_v = apply_on_range(
+                    v,
+                    stack,
+                    false,
+                    false,
+                    &OperateOnCallbacks {
+                        operate_on_object: &|map, re, stack, stack_anchored| {
+                            operate_on_object(map, re, stack, stack_anchored, matches)
                         },
-                    );
-                    match &new_v {
-                        Value::Array(array) => {
-                            if !array.is_empty() {
-                                new_map.insert(k.clone(), new_v.clone());
-                            }
-                        }
-                        Value::Object(object) => {
-                            if !object.is_empty() {
-                                new_map.insert(k.clone(), new_v.clone());
-                            }
-                        } // _ => new_map.insert(k.clone(), new_v),
-                        Value::Null => {}
-                        _ => {
-                            new_map.insert(k.clone(), new_v.clone());
-                        }
-                    }
+                        operate_on_array: &|vec, array_range| {
+                            operate_on_array(vec, array_range, matches)
+                        },
+                        operate_on_string: &|input, re| operate_on_string(input, re, matches),
+                    },
+                );
+                if !is_empty_or_null(&new_v) {
+                    new_map.insert(k, new_v);
                 }
             }
         }
-        if !new_map.is_empty() {
-            return serde_json::Value::Object(new_map);
-        }
-        serde_json::Value::Null
+        object_or_null(new_map)
     }
-    fn operate_on_array(vec: Vec<Value>, array_range: ArrayRange) -> Value {
+    fn operate_on_array(vec: Vec<Value>, array_range: &ArrayRange, matches: &Cell<usize>) -> Value {
         let mut new_vec: Vec<Value> = Vec::new();
-        for (i, val) in vec.iter().enumerate() {
+        for (i, val) in vec.into_iter().enumerate() {
             if i >= array_range.begin && i <= array_range.end {
-                new_vec.push(val.clone());
+                matches.set(matches.get() + 1);
+                new_vec.push(val);
             }
         }
         serde_json::Value::Array(new_vec)
     }
-    fn operate_on_string(input: String, re: Regex) -> Value {
+    fn operate_on_string(input: String, re: &Regex, matches: &Cell<usize>) -> Value {
         if re.find(&input).is_some() {
+            matches.set(matches.get() + 1);
             serde_json::Value::String(input)
         } else {
             serde_json::Value::Null
         }
     }
+    let matches = Cell::new(0);
     let callbacks = OperateOnCallbacks {
-        operate_on_object: &operate_on_object,
-        operate_on_array: &operate_on_array,
-        operate_on_string: &operate_on_string,
+        operate_on_object: &|map, re, stack, stack_anchored| {
+            operate_on_object(map, re, stack, stack_anchored, &matches)
+        },
+        operate_on_array: &|vec, array_range| operate_on_array(vec, array_range, &matches),
+        operate_on_string: &|input, re| operate_on_string(input, re, &matches),
     };
-    apply_on_range(v, stack, false, false, &callbacks)
+    let v = apply_on_range(v, stack, false, false, &callbacks);
+    (v, matches.get())
 }
 
-pub fn delete_on_specified_ranges(v: Value, stack: &[RangeType]) -> Value {
+/// Removes the nodes that match the filter "stack".
+/// Returns the remaining JSON and the number of matches.
+pub fn delete_on_specified_ranges(v: Value, stack: &[RangeType]) -> (Value, usize) {
     if stack.is_empty() {
-        return Value::Null;
+        return (Value::Null, 1);
     }
     fn operate_on_object(
         map: Map<String, Value>,
-        re: Regex,
+        re: &Regex,
         stack: &[RangeType],
         stack_anchored: bool,
+        matches: &Cell<usize>,
     ) -> Value {
         let mut new_map: Map<String, Value> = Map::new();
-        // It was already popped before
-        if stack_anchored {
-            for (k, v) in map {
-                if re.find(&k).is_none() {
-                    new_map.insert(k.clone(), v.clone());
-                }
-            }
-        } else {
-            for (k, v) in map {
-                if re.find(&k).is_none() {
-                    let new_v = apply_on_range(
-                        v.clone(),
-                        stack,
-                        false,
-                        true,
-                        &OperateOnCallbacks {
-                            operate_on_object: &operate_on_object,
-                            operate_on_array: &operate_on_array,
-                            operate_on_string: &operate_on_string,
+        for (k, v) in map {
+            if re.find(&k).is_some() {
+                matches.set(matches.get() + 1);
+            } else if stack_anchored {
+                // It was already popped before
+                new_map.insert(k, v);
+            } else {
+                let new_v = apply_on_range(
+                    v,
+                    stack,
+                    false,
+                    true,
+                    &OperateOnCallbacks {
+                        operate_on_object: &|map, re, stack, stack_anchored| {
+                            operate_on_object(map, re, stack, stack_anchored, matches)
                         },
-                    );
-                    match &new_v {
-                        Value::Array(_) => {
-                            // Allows empty arrays to be returned
-                            new_map.insert(k.clone(), new_v.clone());
-                        }
-                        Value::Object(object) => {
-                            if !object.is_empty() {
-                                new_map.insert(k.clone(), new_v.clone());
-                            }
-                        }
-                        Value::Null => {}
-                        _ => {
-                            new_map.insert(k.clone(), new_v.clone());
-                        }
-                    }
-                } else {
-                    // return Value::Null;
-                    // let new_v = apply_on_range(
-                    //     v.clone(),
-                    //     stack,
-                    //     true,
-                    //     false,
-                    //     &operate_on_object,
-                    //     &operate_on_array,
-                    //     &operate_on_string,
-                    // );
-                    // match &new_v {
-                    //     Value::Array(array) => {
-                    //         if array.len() > 0 {
-                    //             new_map.insert(k.clone(), new_v.clone());
-                    //         }
-                    //     }
-                    //     Value::Object(object) => {
-                    //         if object.len() > 0 {
-                    //             new_map.insert(k.clone(), new_v.clone());
-                    //         }
-                    //     }
-                    //     Value::Null => {}
-                    //     _ => {
-                    //         new_map.insert(k.clone(), new_v.clone());
-                    //     }
-                    // }
+                        operate_on_array: &|vec, array_range| {
+                            operate_on_array(vec, array_range, matches)
+                        },
+                        operate_on_string: &|input, re| operate_on_string(input, re, matches),
+                    },
+                );
+                // Allows empty arrays to be returned
+                let keep = match &new_v {
+                    Value::Object(object) => !object.is_empty(),
+                    Value::Null => false,
+                    _ => true,
+                };
+                if keep {
+                    new_map.insert(k, new_v);
                 }
             }
         }
-        if !new_map.is_empty() {
-            return serde_json::Value::Object(new_map);
-        }
-        serde_json::Value::Null
+        object_or_null(new_map)
     }
-    fn operate_on_array(vec: Vec<Value>, array_range: ArrayRange) -> Value {
+    fn operate_on_array(vec: Vec<Value>, array_range: &ArrayRange, matches: &Cell<usize>) -> Value {
         let mut new_vec: Vec<Value> = Vec::new();
-        for (i, val) in vec.iter().enumerate() {
+        for (i, val) in vec.into_iter().enumerate() {
             if i < array_range.begin || i > array_range.end {
-                new_vec.push(val.clone());
+                new_vec.push(val);
+            } else {
+                matches.set(matches.get() + 1);
             }
         }
         serde_json::Value::Array(new_vec)
     }
-    fn operate_on_string(input: String, re: Regex) -> Value {
+    fn operate_on_string(input: String, re: &Regex, matches: &Cell<usize>) -> Value {
         if re.find(&input).is_some() {
+            matches.set(matches.get() + 1);
             serde_json::Value::Null
         } else {
             serde_json::Value::String(input)
         }
     }
+    let matches = Cell::new(0);
     let callbacks = OperateOnCallbacks {
-        operate_on_object: &operate_on_object,
-        operate_on_array: &operate_on_array,
-        operate_on_string: &operate_on_string,
+        operate_on_object: &|map, re, stack, stack_anchored| {
+            operate_on_object(map, re, stack, stack_anchored, &matches)
+        },
+        operate_on_array: &|vec, array_range| operate_on_array(vec, array_range, &matches),
+        operate_on_string: &|input, re| operate_on_string(input, re, &matches),
     };
-    apply_on_range(v, stack, false, true, &callbacks)
+    let v = apply_on_range(v, stack, false, true, &callbacks);
+    (v, matches.get())
 }
 
 fn keep_or_null(keep_non_matching: bool, value: Value) -> Value {
@@ -293,12 +297,34 @@ fn keep_or_null(keep_non_matching: bool, value: Value) -> Value {
     }
 }
 
+/// An Object left without keys is dropped, like the nodes that didn't match.
+fn object_or_null(map: Map<String, Value>) -> Value {
+    if map.is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::Value::Object(map)
+    }
+}
+
+fn is_empty_or_null(v: &Value) -> bool {
+    match v {
+        Value::Array(array) => array.is_empty(),
+        Value::Object(object) => object.is_empty(),
+        Value::Null => true,
+        _ => false,
+    }
+}
+
+type OperateOnObject<'a> = &'a dyn Fn(Map<String, Value>, &Regex, &[RangeType], bool) -> Value;
+
 struct OperateOnCallbacks<'a> {
-    operate_on_object: &'a dyn Fn(Map<String, Value>, Regex, &[RangeType], bool) -> Value,
-    operate_on_array: &'a dyn Fn(Vec<Value>, ArrayRange) -> Value,
-    operate_on_string: &'a dyn Fn(String, Regex) -> Value,
+    operate_on_object: OperateOnObject<'a>,
+    operate_on_array: &'a dyn Fn(Vec<Value>, &ArrayRange) -> Value,
+    operate_on_string: &'a dyn Fn(String, &Regex) -> Value,
 }
 
+/// Rebuilds the document, handing the nodes the filter "stack" reaches to the callbacks. The
+/// nodes are moved into the new document, never copied.
 fn apply_on_range(
     v: Value,
     stack: &[RangeType],
@@ -309,123 +335,64 @@ fn apply_on_range(
     let Some((stack_head, stack_tail)) = stack.split_first() else {
         return v;
     };
+    // Applies the whole stack again on every child, to find matches further down
+    let apply_on_children = |vec: Vec<Value>| -> Vec<Value> {
+        vec.into_iter()
+            .map(|val| apply_on_range(val, stack, false, keep_non_matching, operate_on_callbacks))
+            .collect()
+    };
+    let apply_on_members = |map: Map<String, Value>| -> Value {
+        let mut new_map: Map<String, Value> = Map::new();
+        for (k, v) in map {
+            let new_v = apply_on_range(v, stack, false, keep_non_matching, operate_on_callbacks);
+            if !new_v.is_null() {
+                new_map.insert(k, new_v);
+            }
+        }
+        object_or_null(new_map)
+    };
     if stack_tail.is_empty() {
         match v {
             Value::Object(current) => match stack_head {
                 RangeType::Key(re) => {
-                    return (operate_on_callbacks.operate_on_object)(
-                        current,
-                        re.to_owned(),
-                        stack,
-                        stack_anchored,
-                    );
+                    (operate_on_callbacks.operate_on_object)(current, re, stack, stack_anchored)
                 }
                 RangeType::Array(_) | RangeType::Value(_) => {
                     if stack_anchored {
                         keep_or_null(keep_non_matching, serde_json::Value::Object(current))
                     } else {
-                        let mut new_map: Map<String, Value> = Map::new();
-                        for (k, v) in &current {
-                            let new_v = apply_on_range(
-                                v.clone(),
-                                stack,
-                                false,
-                                keep_non_matching,
-                                &operate_on_callbacks,
-                            );
-                            if new_v != Value::Null {
-                                new_map.insert(k.clone(), new_v);
-                            }
-                        }
-                        if !new_map.is_empty() {
-                            return serde_json::Value::Object(new_map);
-                        } else {
-                            return serde_json::Value::Null;
-                        }
+                        apply_on_members(current)
                     }
                 }
             },
             Value::String(v) => match stack_head {
                 RangeType::Key(_) => keep_or_null(keep_non_matching, serde_json::Value::String(v)),
-                RangeType::Array(_) => return serde_json::Value::Null,
-                RangeType::Value(re) => {
-                    return (&operate_on_callbacks.operate_on_string)(v, re.to_owned())
-                }
+                RangeType::Array(_) => serde_json::Value::Null,
+                RangeType::Value(re) => (operate_on_callbacks.operate_on_string)(v, re),
             },
             Value::Array(current) => match stack_head {
                 RangeType::Key(_) => {
                     if stack_anchored {
                         keep_or_null(keep_non_matching, serde_json::Value::Array(current))
                     } else {
-                        let mut result: Vec<Value> = Vec::new();
-                        for i in current {
-                            let new_v = apply_on_range(
-                                i.clone(),
-                                stack,
-                                stack_anchored,
-                                keep_non_matching,
-                                &operate_on_callbacks,
-                            );
-                            match &new_v {
-                                Value::Array(array) => {
-                                    if !array.is_empty() {
-                                        result.push(new_v);
-                                    }
-                                }
-                                Value::Object(object) => {
-                                    if !object.is_empty() {
-                                        result.push(new_v);
-                                    }
-                                }
-                                Value::Null => {}
-                                _ => {
-                                    result.push(new_v);
-                                }
-                            }
-                        }
-                        return serde_json::Value::Array(result);
+                        let mut result = apply_on_children(current);
+                        result.retain(|new_v| !is_empty_or_null(new_v));
+                        serde_json::Value::Array(result)
                     }
                 }
                 RangeType::Array(array_range) => {
-                    return (&operate_on_callbacks.operate_on_array)(
-                        current,
-                        array_range.to_owned(),
-                    );
+                    (operate_on_callbacks.operate_on_array)(current, array_range)
                 }
                 RangeType::Value(_) => {
                     if stack_anchored {
                         keep_or_null(keep_non_matching, serde_json::Value::Array(current))
                     } else {
-                        let mut result: Vec<Value> = Vec::new();
-                        for i in current {
-                            let new_v = apply_on_range(
-                                i.clone(),
-                                stack,
-                                stack_anchored,
-                                keep_non_matching,
-                                &operate_on_callbacks,
-                            );
-                            match &new_v {
-                                Value::Array(array) => {
-                                    if !array.is_empty() {
-                                        result.push(new_v);
-                                    }
-                                }
-                                Value::Object(object) => {
-                                    if !object.is_empty() {
-                                        result.push(new_v);
-                                    }
-                                }
-                                Value::Null => {}
-                                _ => {
-                                    result.push(new_v);
-                                }
-                            }
-                        }
+                        let mut result = apply_on_children(current);
+                        result.retain(|new_v| !is_empty_or_null(new_v));
                         if !result.is_empty() {
-                            return serde_json::Value::Array(result);
+                            serde_json::Value::Array(result)
                         } else {
-                            return serde_json::Value::Null;
+                            serde_json::Value::Null
                         }
                     }
                 }
@@ -439,74 +406,42 @@ fn apply_on_range(
             Value::Object(current) => match stack_head {
                 RangeType::Key(re) => {
                     let mut new_map: Map<String, Value> = Map::new();
-                    for (k, v) in &current {
-                        if stack_anchored {
-                            if re.find(k).is_some() {
-                                let new_v = apply_on_range(
-                                    v.clone(),
-                                    stack_tail,
-                                    true,
-                                    keep_non_matching,
-                                    &operate_on_callbacks,
-                                );
-                                if new_v != Value::Null {
-                                    new_map.insert(k.clone(), new_v);
-                                }
-                            } else if keep_non_matching {
-                                new_map.insert(k.clone(), v.clone());
-                            }
-                        } else if re.find(k).is_some() {
+                    for (k, v) in current {
+                        if re.find(&k).is_some() {
                             let new_v = apply_on_range(
-                                v.clone(),
+                                v,
                                 stack_tail,
                                 true,
                                 keep_non_matching,
-                                &operate_on_callbacks,
+                                operate_on_callbacks,
                             );
-                            if new_v != Value::Null {
-                                new_map.insert(k.clone(), new_v);
+                            if !new_v.is_null() {
+                                new_map.insert(k, new_v);
+                            }
+                        } else if stack_anchored {
+                            if keep_non_matching {
+                                new_map.insert(k, v);
                             }
                         } else {
                             let new_v = apply_on_range(
-                                v.clone(),
+                                v,
                                 stack,
                                 false,
                                 keep_non_matching,
-                                &operate_on_callbacks,
+                                operate_on_callbacks,
                             );
-                            if new_v != Value::Null {
-                                new_map.insert(k.clone(), new_v);
+                            if !new_v.is_null() {
+                                new_map.insert(k, new_v);
                             }
                         }
                     }
-                    if !new_map.is_empty() {
-                        return serde_json::Value::Object(new_map);
-                    } else {
-                        return serde_json::Value::Null;
-                    }
+                    object_or_null(new_map)
                 }
                 RangeType::Array(_) => {
                     if stack_anchored {
                         keep_or_null(keep_non_matching, serde_json::Value::Object(current))
                     } else {
-                        let mut new_map: Map<String, Value> = Map::new();
-                        for (k, v) in &current {
-                            let new_v = apply_on_range(
-                                v.clone(),
-                                stack,
-                                false,
-                                keep_non_matching,
-                                &operate_on_callbacks,
-                            );
-                            if new_v != Value::Null {
-                                new_map.insert(k.clone(), new_v);
-                            }
-                        }
-                        if !new_map.is_empty() {
-                            return serde_json::Value::Object(new_map);
-                        } else {
-                            return serde_json::Value::Null;
-                        }
+                        apply_on_members(current)
                     }
                 }
                 RangeType::Value(_) => {
@@ -519,43 +454,30 @@ fn apply_on_range(
                     if stack_anchored {
                         keep_or_null(keep_non_matching, serde_json::Value::Array(v))
                     } else {
-                        let mut new_vec: Vec<Value> = Vec::new();
-                        for val in &v {
-                            let new_v = apply_on_range(
-                                val.clone(),
-                                stack,
-                                false,
-                                keep_non_matching,
-                                &operate_on_callbacks,
-                            );
-                            if new_v != serde_json::Value::Null {
-                                new_vec.push(new_v)
-                            }
-                        }
+                        let mut new_vec = apply_on_children(v);
+                        new_vec.retain(|new_v| !new_v.is_null());
                         if !new_vec.is_empty() {
                             return serde_json::Value::Array(new_vec);
                         }
-                        return serde_json::Value::Null;
+                        serde_json::Value::Null
                     }
                 }
                 RangeType::Array(array_range) => {
                     let mut new_vec: Vec<Value> = Vec::new();
-                    for (i, val) in v.iter().enumerate() {
+                    for (i, val) in v.into_iter().enumerate() {
                         if i >= array_range.begin && i <= array_range.end {
                             new_vec.push(apply_on_range(
-                                val.clone(),
+                                val,
                                 stack_tail,
                                 true,
                                 keep_non_matching,
-                                &operate_on_callbacks,
+                                operate_on_callbacks,
                             ));
-                        } else if keep_non_matching && {
-                            i < array_range.begin || i > array_range.end
-                        } {
-                            new_vec.push(val.clone());
+                        } else if keep_non_matching {
+                            new_vec.push(val);
                         }
                     }
-                    return serde_json::Value::Array(new_vec);
+                    serde_json::Value::Array(new_vec)
                 }
                 RangeType::Value(_) => keep_or_null(keep_non_matching, serde_json::Value::Array(v)),
             },
@@ -572,79 +494,91 @@ pub fn substitute_values_on_specified_ranges(
     stack: &[RangeType],
     old_regexp: &Regex,
     replace_with: &str,
-) -> Value {
+) -> (Value, usize) {
+    fn substitute(
+        v: Value,
+        old_regexp: &Regex,
+        replace_with: &String,
+        replaced: &Cell<usize>,
+    ) -> Value {
+        let (new_v, new_v_replaced) = substitute_values(v, old_regexp, replace_with);
+        replaced.set(replaced.get() + new_v_replaced);
+        new_v
+    }
+    /// Whether `apply_on_range` would leave something of `v` (anything but `null`) when looking
+    /// for the range below it. Answers without rebuilding, nor copying, `v`.
+    fn reaches(v: &Value, stack: &[RangeType], stack_anchored: bool) -> bool {
+        let Some((stack_head, stack_tail)) = stack.split_first() else {
+            return !v.is_null();
+        };
+        let any_child = |mut children: std::slice::Iter<Value>| {
+            children.any(|child| reaches(child, stack, false))
+        };
+        match (v, stack_head) {
+            (Value::Null, _) => false,
+            (Value::Object(map), RangeType::Key(re)) if !stack_tail.is_empty() => {
+                map.iter().any(|(k, v)| {
+                    if re.find(k).is_some() {
+                        reaches(v, stack_tail, true)
+                    } else {
+                        stack_anchored || reaches(v, stack, false)
+                    }
+                })
+            }
+            (Value::Object(map), RangeType::Array(_))
+            | (Value::Object(map), RangeType::Value(_)) => {
+                if stack_anchored
+                    || (!stack_tail.is_empty() && matches!(stack_head, RangeType::Value(_)))
+                {
+                    return true;
+                }
+                map.values().any(|child| reaches(child, stack, false))
+            }
+            (Value::String(_), RangeType::Array(_)) => !stack_tail.is_empty(),
+            (Value::Array(vec), RangeType::Key(_)) => {
+                stack_anchored || stack_tail.is_empty() || any_child(vec.iter())
+            }
+            (Value::Array(vec), RangeType::Value(_)) => {
+                stack_anchored || !stack_tail.is_empty() || any_child(vec.iter())
+            }
+            _ => true,
+        }
+    }
     fn operate_on_object(
         map: Map<String, Value>,
-        re: Regex,
+        re: &Regex,
         stack: &[RangeType],
         stack_anchored: bool,
-        old_regexp: Regex,
-        replace_with: String,
+        old_regexp: &Regex,
+        replace_with: &String,
+        replaced: &Cell<usize>,
     ) -> Value {
-        if stack_anchored {
-            let mut new_map = Map::new();
-            for (k, v) in map {
-                if re.find(&k).is_some() {
-                    new_map.insert(k, substitute_values(v, &old_regexp, &replace_with));
-                } else {
-                    new_map.insert(k, v);
-                }
+        let mut new_map = Map::new();
+        for (k, v) in map {
+            if re.find(&k).is_some() {
+                // mal, se puede rompe con doble llave?
+                new_map.insert(k, substitute(v, old_regexp, replace_with, replaced));
+            } else if stack_anchored {
+                new_map.insert(k, v);
+            } else if reaches(&v, stack, false) {
+                new_map.insert(k, substitute(v, old_regexp, replace_with, replaced));
             }
-            Value::Object(new_map)
-        } else {
-            let mut new_map = Map::new();
-            for (k, v) in map {
-                if re.find(&k).is_some() {
-                    // mal, se puede rompe con doble llave?
-                    new_map.insert(k, substitute_values(v, &old_regexp, &replace_with));
-                } else {
-                    let new_v = apply_on_range(
-                        v.clone(),
-                        stack,
-                        false,
-                        true,
-                        &OperateOnCallbacks {
-                            operate_on_object: &|map, re, stack, stack_anchored| {
-                                operate_on_object(
-                                    map,
-                                    re,
-                                    stack,
-                                    stack_anchored,
-                                    old_regexp.clone(),
-                                    replace_with.clone(),
-                                )
-                            },
-                            operate_on_array: &|vec, array_range| {
-                                operate_on_array(
-                                    vec,
-                                    array_range,
-                                    old_regexp.clone(),
-                                    replace_with.clone(),
-                                )
-                            },
-                            operate_on_string: &|s, _re| Value::String(s), // strings aren't a range target here
-                        },
-                    );
-                    if new_v != serde_json::Value::Null {
-                        new_map.insert(k, substitute_values(v, &old_regexp, &replace_with));
-                    }
-                }
-            }
-            Value::Object(new_map)
         }
+        Value::Object(new_map)
     }
     fn operate_on_array(
         vec: Vec<Value>,
-        array_range: ArrayRange,
-        old_regexp: Regex,
-        replace_with: String,
+        array_range: &ArrayRange,
+        old_regexp: &Regex,
+        replace_with: &String,
+        replaced: &Cell<usize>,
     ) -> Value {
         let new_vec = vec
             .into_iter()
             .enumerate()
             .map(|(i, val)| {
                 if i >= array_range.begin && i <= array_range.end {
-                    substitute_values(val, &old_regexp, &replace_with)
+                    substitute(val, old_regexp, replace_with, replaced)
                 } else {
                     val
                 }
@@ -654,17 +588,20 @@ pub fn substitute_values_on_specified_ranges(
     }
     fn operate_on_string(
         string: String,
-        value_range: Regex,
-        old_regexp: Regex,
-        replace_with: String,
+        value_range: &Regex,
+        old_regexp: &Regex,
+        replace_with: &String,
+        replaced: &Cell<usize>,
     ) -> Value {
         if value_range.is_match(&string) {
-            return substitute_values(Value::String(string), &old_regexp, &replace_with);
+            return substitute(Value::String(string), old_regexp, replace_with, replaced);
         }
         Value::String(string)
     }
 
-    apply_on_range(
+    let replaced = Cell::new(0);
+    let replace_with = replace_with.to_owned();
+    let v = apply_on_range(
         v,
         stack,
         false,
@@ -676,23 +613,20 @@ pub fn substitute_values_on_specified_ranges(
                     re,
                     stack,
                     stack_anchored,
-                    old_regexp.clone(),
-                    replace_with.to_owned(),
+                    old_regexp,
+                    &replace_with,
+                    &replaced,
                 )
             },
             operate_on_array: &|vec, array_range| {
-                operate_on_array(
-                    vec,
-                    array_range,
-                    old_regexp.clone(),
-                    replace_with.to_owned(),
-                )
+                operate_on_array(vec, array_range, old_regexp, &replace_with, &replaced)
             },
-            operate_on_string: &|s, _re| {
-                operate_on_string(s, _re, old_regexp.clone(), replace_with.to_owned())
+            operate_on_string: &|s, re| {
+                operate_on_string(s, re, old_regexp, &replace_with, &replaced)
             },
         },
-    )
+    );
+    (v, replaced.get())
 }
 
 pub fn substitute_keys_on_specified_ranges(
@@ -700,8 +634,14 @@ pub fn substitute_keys_on_specified_ranges(
     stack: &[RangeType],
     replace_regex: &Regex,
     replace_with: &String,
-) -> Value {
-    apply_on_range(
+) -> (Value, usize) {
+    let replaced = Cell::new(0);
+    let substitute = |v| {
+        let (new_v, new_v_replaced) = substitute_keys(v, replace_regex, replace_with);
+        replaced.set(replaced.get() + new_v_replaced);
+        new_v
+    };
+    let v = apply_on_range(
         v,
         stack,
         false,
@@ -711,9 +651,11 @@ pub fn substitute_keys_on_specified_ranges(
                 let mut new_map: Map<String, Value> = Map::new();
                 for (k, v) in map {
                     if re.find(&k).is_some() {
+                        if replace_regex.is_match(&k) {
+                            replaced.set(replaced.get() + 1);
+                        }
                         let new_key = replace_regex.replace_all(&k, replace_with).into_owned();
-                        let new_v = substitute_keys(v, replace_regex, replace_with);
-                        new_map.insert(new_key, new_v);
+                        new_map.insert(new_key, substitute(v));
                     } else {
                         new_map.insert(k, v);
                     }
@@ -726,7 +668,7 @@ pub fn substitute_keys_on_specified_ranges(
                     .enumerate()
                     .map(|(i, val)| {
                         if i >= array_range.begin && i <= array_range.end {
-                            substitute_keys(val, replace_regex, replace_with)
+                            substitute(val)
                         } else {
                             val
                         }
@@ -736,5 +678,335 @@ pub fn substitute_keys_on_specified_ranges(
             },
             operate_on_string: &|s, _re| Value::String(s), // strings aren't a range target here
         },
-    )
+    );
+    (v, replaced.get())
+}
+
+/// Merges `other` into `v`. Objects receive the keys of `other` (recursively when `deep` is set),
+/// arrays are extended with the elements of an array `other` or get `other` appended to them.
+/// Any other value is left as is.
+pub fn merge_values(v: Value, other: &Value, deep: bool) -> Value {
+    match (v, other) {
+        (Value::Object(mut map), Value::Object(other_map)) => {
+            for (k, other_v) in other_map {
+                // Existing keys are updated where they are, new keys go at the end
+                match map.get_mut(k) {
+                    Some(old_v) if deep && old_v.is_object() && other_v.is_object() => {
+                        *old_v = merge_values(std::mem::take(old_v), other_v, deep);
+                    }
+                    _ => {
+                        map.insert(k.clone(), other_v.clone());
+                    }
+                }
+            }
+            Value::Object(map)
+        }
+        (Value::Array(mut vec), Value::Array(other_vec)) => {
+            vec.extend(other_vec.iter().cloned());
+            Value::Array(vec)
+        }
+        (Value::Array(mut vec), _) => {
+            vec.push(other.clone());
+            Value::Array(vec)
+        }
+        (v, _) => v,
+    }
+}
+
+/// Merges `other` into every node that matches the filter "stack" (the root if the stack is empty).
+/// Returns the new JSON and the number of matches.
+pub fn read_on_specified_ranges(
+    v: Value,
+    stack: &[RangeType],
+    other: &Value,
+    deep: bool,
+) -> (Value, usize) {
+    if stack.is_empty() {
+        return (merge_values(v, other, deep), 1);
+    }
+    transform_on_specified_ranges(v, stack, &|_, node| merge_values(node, other, deep))
+}
+
+/// The hold space of `h`, `H`, `g`, `G` and `x`. It holds the matches themselves, and the keys
+/// they were found through so that `G` can merge them into an Object under those keys.
+#[derive(Default)]
+pub struct HoldSpace {
+    /// The match held, or an Array of the matches once `H` appended to it.
+    pub value: Value,
+    /// Number of matches held.
+    len: usize,
+    /// The key of every match held, `None` if one of them wasn't found through a key.
+    keys: Option<Vec<String>>,
+}
+
+impl HoldSpace {
+    fn push(&mut self, key: Option<&str>, node: Value) {
+        self.value = match (self.len, std::mem::take(&mut self.value)) {
+            (0, _) => node,
+            (1, held) => Value::Array(vec![held, node]),
+            (_, Value::Array(mut vec)) => {
+                vec.push(node);
+                Value::Array(vec)
+            }
+            (_, held) => held,
+        };
+        self.keys = match (self.len, self.keys.take(), key) {
+            (0, _, Some(key)) => Some(vec![key.to_string()]),
+            (_, Some(mut keys), Some(key)) => {
+                keys.push(key.to_string());
+                Some(keys)
+            }
+            _ => None,
+        };
+        self.len += 1;
+    }
+
+    /// What `G` appends to a node: the matches under their keys if they were found through keys
+    /// and the node is an Object, the same way `r` does otherwise.
+    fn append_to(&self, node: Value) -> Value {
+        match (node, &self.keys) {
+            (Value::Object(mut map), Some(keys)) => {
+                if let [key] = keys.as_slice() {
+                    map.insert(key.clone(), self.value.clone());
+                } else if let Value::Array(vec) = &self.value {
+                    map.extend(keys.iter().cloned().zip(vec.iter().cloned()));
+                }
+                Value::Object(map)
+            }
+            (node, _) => merge_values(node, &self.value, false),
+        }
+    }
+}
+
+/// Copies the nodes that match the filter "stack" into the hold space (`h`), or appends them to
+/// it (`H`). The document itself is left untouched, the number of matches is returned with it.
+pub fn hold_on_specified_ranges(
+    v: Value,
+    stack: &[RangeType],
+    hold: &mut HoldSpace,
+    append: bool,
+) -> (Value, usize) {
+    if !append {
+        *hold = HoldSpace::default();
+    }
+    if stack.is_empty() {
+        hold.push(None, v.clone());
+        return (v, 1);
+    }
+    let hold_space = RefCell::new(std::mem::take(hold));
+    let (v, matches) = transform_on_specified_ranges(v, stack, &|key, node| {
+        hold_space.borrow_mut().push(key, node.clone());
+        node
+    });
+    *hold = hold_space.into_inner();
+    (v, matches)
+}
+
+/// Replaces the nodes that match the filter "stack" with the hold space (`g`), or appends the
+/// hold space to them (`G`). Returns the new JSON and the number of matches.
+pub fn get_on_specified_ranges(
+    v: Value,
+    stack: &[RangeType],
+    hold: &HoldSpace,
+    append: bool,
+) -> (Value, usize) {
+    let get = |node| {
+        if append {
+            hold.append_to(node)
+        } else {
+            hold.value.clone()
+        }
+    };
+    if stack.is_empty() {
+        return (get(v), 1);
+    }
+    transform_on_specified_ranges(v, stack, &|_, node| get(node))
+}
+
+/// Exchanges the nodes that match the filter "stack" with the hold space (`x`).
+/// Returns the new JSON and the number of matches.
+pub fn exchange_on_specified_ranges(
+    v: Value,
+    stack: &[RangeType],
+    hold: &mut HoldSpace,
+) -> (Value, usize) {
+    let old_hold = std::mem::take(hold).value;
+    if stack.is_empty() {
+        hold.push(None, v);
+        return (old_hold, 1);
+    }
+    let hold_space = RefCell::new(HoldSpace::default());
+    let (v, matches) = transform_on_specified_ranges(v, stack, &|key, node| {
+        hold_space.borrow_mut().push(key, node);
+        old_hold.clone()
+    });
+    *hold = hold_space.into_inner();
+    (v, matches)
+}
+
+/// Replaces every node that matches the filter "stack" with the result of `transform`,
+/// keeping the rest of the document untouched. Returns the new JSON and the number of matches.
+fn transform_on_specified_ranges(
+    v: Value,
+    stack: &[RangeType],
+    transform: &dyn Fn(Option<&str>, Value) -> Value,
+) -> (Value, usize) {
+    fn operate_on_object(
+        map: Map<String, Value>,
+        re: &Regex,
+        stack: &[RangeType],
+        stack_anchored: bool,
+        transform: &dyn Fn(Option<&str>, Value) -> Value,
+    ) -> Value {
+        let mut new_map = Map::new();
+        for (k, v) in map {
+            if re.find(&k).is_some() {
+                let new_v = transform(Some(&k), v);
+                new_map.insert(k, new_v);
+            } else if stack_anchored {
+                new_map.insert(k, v);
+            } else {
+                let new_v = apply_on_range(
+                    v,
+                    stack,
+                    false,
+                    true,
+                    &OperateOnCallbacks {
+                        operate_on_object: &|map, re, stack, stack_anchored| {
+                            operate_on_object(map, re, stack, stack_anchored, transform)
+                        },
+                        operate_on_array: &|vec, array_range| {
+                            operate_on_array(vec, array_range, transform)
+                        },
+                        operate_on_string: &|s, re| operate_on_string(s, re, transform),
+                    },
+                );
+                new_map.insert(k, new_v);
+            }
+        }
+        Value::Object(new_map)
+    }
+    fn operate_on_array(
+        vec: Vec<Value>,
+        array_range: &ArrayRange,
+        transform: &dyn Fn(Option<&str>, Value) -> Value,
+    ) -> Value {
+        let new_vec = vec
+            .into_iter()
+            .enumerate()
+            .map(|(i, val)| {
+                if i >= array_range.begin && i <= array_range.end {
+                    transform(None, val)
+                } else {
+                    val
+                }
+            })
+            .collect();
+        Value::Array(new_vec)
+    }
+    fn operate_on_string(
+        string: String,
+        re: &Regex,
+        transform: &dyn Fn(Option<&str>, Value) -> Value,
+    ) -> Value {
+        if re.is_match(&string) {
+            return transform(None, Value::String(string));
+        }
+        Value::String(string)
+    }
+
+    let matches = Cell::new(0);
+    let transform = &|key: Option<&str>, node| {
+        matches.set(matches.get() + 1);
+        transform(key, node)
+    };
+    let v = apply_on_range(
+        v,
+        stack,
+        false,
+        true, // keep non-matching nodes, only the matches are transformed
+        &OperateOnCallbacks {
+            operate_on_object: &|map, re, stack, stack_anchored| {
+                operate_on_object(map, re, stack, stack_anchored, transform)
+            },
+            operate_on_array: &|vec, array_range| operate_on_array(vec, array_range, transform),
+            operate_on_string: &|s, re| operate_on_string(s, re, transform),
+        },
+    );
+    (v, matches.get())
+}
+
+/// Counts the nodes that match the filter "stack". The root is the only match of an empty stack.
+/// Finds the same matches as `transform_on_specified_ranges`, without copying the document.
+#[cfg(test)]
+pub fn count_matches(v: &Value, stack: &[RangeType]) -> usize {
+    count_matches_up_to(v, stack, usize::MAX)
+}
+
+/// Whether a node matches the filter "stack", stopping at the first match.
+pub fn matches_any(v: &Value, stack: &[RangeType]) -> bool {
+    count_matches_up_to(v, stack, 1) > 0
+}
+
+/// Counts the matches, stopping once there are `limit` of them.
+fn count_matches_up_to(v: &Value, stack: &[RangeType], limit: usize) -> usize {
+    fn sum_up_to(counts: impl Iterator<Item = usize>, limit: usize) -> usize {
+        let mut total = 0;
+        for count in counts {
+            total += count;
+            if total >= limit {
+                break;
+            }
+        }
+        total
+    }
+    fn count(v: &Value, stack: &[RangeType], stack_anchored: bool, limit: usize) -> usize {
+        let Some((stack_head, stack_tail)) = stack.split_first() else {
+            return 0;
+        };
+        let count_in_children = |children: &mut dyn Iterator<Item = &Value>| -> usize {
+            if stack_anchored {
+                return 0;
+            }
+            sum_up_to(
+                children.map(|child| count(child, stack, false, limit)),
+                limit,
+            )
+        };
+        match (v, stack_head) {
+            (Value::Object(map), RangeType::Key(re)) => sum_up_to(
+                map.iter().map(|(k, v)| {
+                    if re.find(k).is_none() {
+                        count_in_children(&mut std::iter::once(v))
+                    } else if stack_tail.is_empty() {
+                        1
+                    } else {
+                        count(v, stack_tail, true, limit)
+                    }
+                }),
+                limit,
+            ),
+            (Value::Object(map), _) => count_in_children(&mut map.values()),
+            (Value::String(s), RangeType::Value(re)) => usize::from(re.is_match(s)),
+            (Value::Array(vec), RangeType::Array(array_range)) => sum_up_to(
+                vec.iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i >= array_range.begin && *i <= array_range.end)
+                    .map(|(_, val)| {
+                        if stack_tail.is_empty() {
+                            1
+                        } else {
+                            count(val, stack_tail, true, limit)
+                        }
+                    }),
+                limit,
+            ),
+            (Value::Array(vec), _) => count_in_children(&mut vec.iter()),
+            _ => 0,
+        }
+    }
+    if stack.is_empty() {
+        return 1;
+    }
+    count(v, stack, false, limit)
 }
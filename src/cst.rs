@@ -0,0 +1,513 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A JSON value along with the bytes it spans in the source text.
+pub struct Node {
+    pub span: Range<usize>,
+    pub kind: NodeKind,
+}
+pub enum NodeKind {
+    Object(Vec<Member>),
+    Array(Vec<Node>),
+    /// String, Number, Bool or Null: its text is the span in the source.
+    Scalar,
+}
+pub struct Member {
+    pub key: String,
+    pub key_span: Range<usize>,
+    pub value: Node,
+}
+
+/// Parses a JSON document into a concrete syntax tree, keeping the position of every value.
+/// When `lenient`, comments and trailing commas (JSONC) are allowed, and kept by `render`.
+pub fn parse(source: &str, lenient: bool) -> Result<Node> {
+    let mut parser = CstParser {
+        source,
+        pos: 0,
+        lenient,
+    };
+    parser.skip_whitespace();
+    let node = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.pos < source.len() {
+        return Err(parser.error("Trailing characters after the JSON document"));
+    }
+    Ok(node)
+}
+
+/// Builds the `Value` represented by the node.
+pub fn to_value(source: &str, node: &Node) -> Result<Value> {
+    match &node.kind {
+        NodeKind::Object(members) => {
+            let mut map = Map::new();
+            for member in members {
+                map.insert(member.key.clone(), to_value(source, &member.value)?);
+            }
+            Ok(Value::Object(map))
+        }
+        NodeKind::Array(elements) => Ok(Value::Array(
+            elements
+                .iter()
+                .map(|element| to_value(source, element))
+                .collect::<Result<_>>()?,
+        )),
+        NodeKind::Scalar => serde_json::from_str(&source[node.span.clone()])
+            .context("Could not parse value into JSON"),
+    }
+}
+
+/// Writes `new` as an edit of the document parsed into `root`: whatever didn't change keeps
+/// its original text (indentation, spacing, number formatting...), and only the values that
+/// changed are written again.
+pub fn render(source: &str, root: &Node, new: &Value) -> Result<String> {
+    let renderer = Renderer {
+        source,
+        indent_unit: detect_indent_unit(source, root),
+    };
+    let mut out = String::from(&source[..root.span.start]);
+    out.push_str(&renderer.render(root, new, false)?);
+    out.push_str(&source[root.span.end..]);
+    Ok(out)
+}
+
+struct CstParser<'a> {
+    source: &'a str,
+    pos: usize,
+    lenient: bool,
+}
+
+impl CstParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.source.as_bytes().get(self.pos).copied()
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        let mut pos = self.pos.min(self.source.len());
+        while !self.source.is_char_boundary(pos) {
+            pos -= 1;
+        }
+        let before = &self.source[..pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        anyhow::anyhow!("{message} at line {line} column {column}")
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            let rest = &self.source[self.pos..];
+            if let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+                self.pos += 1;
+            } else if self.lenient && rest.starts_with("//") {
+                self.pos += rest.find('\n').unwrap_or(rest.len());
+            } else if self.lenient && rest.starts_with("/*") {
+                self.pos += rest.find("*/").map_or(rest.len(), |end| end + 2);
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Skips the comma after a member or an element, and tells if it was a trailing comma.
+    fn skip_comma(&mut self, closing: u8) -> bool {
+        self.pos += 1;
+        if !self.lenient {
+            return false;
+        }
+        self.skip_whitespace();
+        if self.peek() == Some(closing) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("Expected '{}'", byte as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn parse_value(&mut self) -> Result<Node> {
+        match self.peek() {
+            Some(b'{') => self.parse_object(),
+            Some(b'[') => self.parse_array(),
+            Some(b'"') => {
+                let span = self.parse_string()?;
+                Ok(Node {
+                    span,
+                    kind: NodeKind::Scalar,
+                })
+            }
+            Some(_) => self.parse_literal(),
+            None => Err(self.error("Unexpected end of the JSON document")),
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Node> {
+        let start = self.pos;
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Node {
+                span: start..self.pos,
+                kind: NodeKind::Object(members),
+            });
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("Expected an Object key"));
+            }
+            let key_span = self.parse_string()?;
+            let key = serde_json::from_str(&self.source[key_span.clone()])
+                .map_err(|_| self.error("Invalid Object key"))?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            self.skip_whitespace();
+            let value = self.parse_value()?;
+            members.push(Member {
+                key,
+                key_span,
+                value,
+            });
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => {
+                    if self.skip_comma(b'}') {
+                        break;
+                    }
+                }
+                Some(b'}') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+        Ok(Node {
+            span: start..self.pos,
+            kind: NodeKind::Object(members),
+        })
+    }
+
+    fn parse_array(&mut self) -> Result<Node> {
+        let start = self.pos;
+        self.expect(b'[')?;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Node {
+                span: start..self.pos,
+                kind: NodeKind::Array(elements),
+            });
+        }
+        loop {
+            self.skip_whitespace();
+            elements.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => {
+                    if self.skip_comma(b']') {
+                        break;
+                    }
+                }
+                Some(b']') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(self.error("Expected ',' or ']'")),
+            }
+        }
+        Ok(Node {
+            span: start..self.pos,
+            kind: NodeKind::Array(elements),
+        })
+    }
+
+    fn parse_string(&mut self) -> Result<Range<usize>> {
+        let start = self.pos;
+        self.expect(b'"')?;
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(start..self.pos);
+                }
+                Some(b'\\') => self.pos += 2,
+                Some(_) => self.pos += 1,
+                None => return Err(self.error("Unterminated String")),
+            }
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Node> {
+        let start = self.pos;
+        while let Some(byte) = self.peek() {
+            if matches!(
+                byte,
+                b',' | b'}' | b']' | b'/' | b' ' | b'\t' | b'\n' | b'\r'
+            ) {
+                break;
+            }
+            self.pos += 1;
+        }
+        let text = &self.source[start..self.pos];
+        match serde_json::from_str::<Value>(text) {
+            Ok(Value::Number(_) | Value::Bool(_) | Value::Null) => Ok(Node {
+                span: start..self.pos,
+                kind: NodeKind::Scalar,
+            }),
+            _ => {
+                self.pos = start;
+                Err(self.error(&format!("Invalid value {text}")))
+            }
+        }
+    }
+}
+
+struct Renderer<'a> {
+    source: &'a str,
+    /// The indentation added at every level of the document, reused for the new values.
+    indent_unit: String,
+}
+
+impl Renderer<'_> {
+    /// Writes `new` in place of `node`, on several lines if `node` or its parent spans several.
+    fn render(&self, node: &Node, new: &Value, multiline: bool) -> Result<String> {
+        match (&node.kind, new) {
+            (NodeKind::Object(members), Value::Object(map)) if !members.is_empty() => {
+                self.render_object(node, members, map)
+            }
+            (NodeKind::Array(elements), Value::Array(vec)) if !elements.is_empty() => {
+                self.render_array(node, elements, vec)
+            }
+            _ => {
+                if to_value(self.source, node)? == *new {
+                    return Ok(self.source[node.span.clone()].to_string());
+                }
+                let multiline = multiline || self.is_multiline(node);
+                self.write_new(node.span.start, new, multiline)
+            }
+        }
+    }
+
+    fn render_object(
+        &self,
+        node: &Node,
+        members: &[Member],
+        map: &Map<String, Value>,
+    ) -> Result<String> {
+        let mut old_by_key = HashMap::new();
+        for (i, member) in members.iter().enumerate() {
+            old_by_key.insert(member.key.as_str(), i);
+        }
+        // Index of the old member each new entry comes from
+        let mut pairs: Vec<Option<usize>> = map
+            .keys()
+            .map(|k| old_by_key.get(k.as_str()).copied())
+            .collect();
+        let unpaired_old: Vec<usize> = (0..members.len())
+            .filter(|i| !pairs.contains(&Some(*i)))
+            .collect();
+        let unpaired_new: Vec<usize> = (0..pairs.len()).filter(|i| pairs[*i].is_none()).collect();
+        // As many keys disappeared as appeared: they were renamed (`S`) where they were
+        if unpaired_old.len() == unpaired_new.len() {
+            for (old, new) in unpaired_old.into_iter().zip(unpaired_new) {
+                pairs[new] = Some(old);
+            }
+        }
+
+        let mut items = Vec::new();
+        for ((k, v), pair) in map.iter().zip(pairs) {
+            let text = match pair {
+                Some(i) => {
+                    let member = &members[i];
+                    let key = if member.key == *k {
+                        self.source[member.key_span.clone()].to_string()
+                    } else {
+                        serde_json::to_string(k)?
+                    };
+                    key + &self.source[member.key_span.end..member.value.span.start]
+                        + &self.render(&member.value, v, self.is_multiline(node))?
+                }
+                None => {
+                    let key_start = members[0].key_span.start;
+                    let colon = &self.source[members[0].key_span.end..members[0].value.span.start];
+                    serde_json::to_string(k)?
+                        + colon
+                        + &self.write_new(key_start, v, self.is_multiline(node))?
+                }
+            };
+            items.push((pair, text));
+        }
+        let separators: Vec<&str> = members
+            .windows(2)
+            .map(|pair| &self.source[pair[0].value.span.end..pair[1].key_span.start])
+            .collect();
+        let prefix = &self.source[node.span.start..members[0].key_span.start];
+        let suffix = &self.source[members[members.len() - 1].value.span.end..node.span.end];
+        Ok(self.join(node, prefix, &separators, items, suffix))
+    }
+
+    fn render_array(&self, node: &Node, elements: &[Node], vec: &[Value]) -> Result<String> {
+        let mut pairs: Vec<Option<usize>> = (0..vec.len())
+            .map(|i| (i < elements.len()).then_some(i))
+            .collect();
+        if vec.len() < elements.len() {
+            // Elements were removed: find the ones that are left, if nothing else changed
+            let mut subsequence = Vec::new();
+            let mut i = 0;
+            for new in vec {
+                while i < elements.len() && to_value(self.source, &elements[i])? != *new {
+                    i += 1;
+                }
+                if i == elements.len() {
+                    break;
+                }
+                subsequence.push(Some(i));
+                i += 1;
+            }
+            if subsequence.len() == vec.len() {
+                pairs = subsequence;
+            }
+        }
+
+        let mut items = Vec::new();
+        for (v, pair) in vec.iter().zip(pairs) {
+            let text = match pair {
+                Some(i) => self.render(&elements[i], v, self.is_multiline(node))?,
+                None => self.write_new(elements[0].span.start, v, self.is_multiline(node))?,
+            };
+            items.push((pair, text));
+        }
+        let separators: Vec<&str> = elements
+            .windows(2)
+            .map(|pair| &self.source[pair[0].span.end..pair[1].span.start])
+            .collect();
+        let prefix = &self.source[node.span.start..elements[0].span.start];
+        let suffix = &self.source[elements[elements.len() - 1].span.end..node.span.end];
+        Ok(self.join(node, prefix, &separators, items, suffix))
+    }
+
+    /// Writes the items of a container between its original opening and closing text. Items
+    /// keep the separator that was in front of them, new ones get the first separator. What
+    /// follows an item on its line (`, // comment`) stays with the item.
+    fn join(
+        &self,
+        node: &Node,
+        prefix: &str,
+        separators: &[&str],
+        items: Vec<(Option<usize>, String)>,
+        suffix: &str,
+    ) -> String {
+        if items.is_empty() {
+            let source = &self.source[node.span.clone()];
+            return format!("{}{}", &source[..1], &source[source.len() - 1..]);
+        }
+        let default_separator = match (separators.first(), prefix.rfind('\n')) {
+            (Some(separator), _) if separator.contains('\n') => {
+                format!(",{}", split_separator(separator).1)
+            }
+            (Some(separator), _) => separator.to_string(),
+            (None, Some(newline)) => format!(",{}", &prefix[newline..]),
+            (None, None) => format!(",{}", &prefix[1..]),
+        };
+        let mut out = prefix.to_string();
+        let mut previous = None;
+        for (i, (pair, text)) in items.into_iter().enumerate() {
+            if i > 0 {
+                match (previous, pair) {
+                    (Some(a), Some(b)) if b == a + 1 => out.push_str(separators[a]),
+                    // Items in between were removed
+                    (Some(a), Some(b)) if b > 0 && a < separators.len() => {
+                        out.push_str(split_separator(separators[a]).0);
+                        out.push_str(split_separator(separators[b - 1]).1);
+                    }
+                    (_, Some(b)) if b > 0 => out.push_str(separators[b - 1]),
+                    _ => out.push_str(&default_separator),
+                }
+            }
+            out.push_str(&text);
+            previous = pair;
+        }
+        // The items after the last one were removed, it loses its comma (and so does the end of
+        // the container, if it had a trailing comma)
+        if let Some(a) = previous.filter(|a| *a < separators.len()) {
+            let comment = split_separator(separators[a]).0.replacen(',', "", 1);
+            out.push_str(&comment);
+            let end = match split_separator(suffix) {
+                ("", suffix) if suffix.trim_start().starts_with(',') => suffix.replacen(',', "", 1),
+                ("", suffix) => suffix.to_string(),
+                (_, end) => end.to_string(),
+            };
+            // A `//` comment ends with its line
+            if comment.contains("//") && !end.starts_with('\n') {
+                out.push('\n');
+            }
+            out.push_str(&end);
+            return out;
+        }
+        out.push_str(suffix);
+        out
+    }
+
+    fn is_multiline(&self, node: &Node) -> bool {
+        self.source[node.span.clone()].contains('\n')
+    }
+
+    /// Writes a new value that will start at `pos`, indented like the line of `pos`.
+    fn write_new(&self, pos: usize, new: &Value, multiline: bool) -> Result<String> {
+        if !multiline || !(new.is_object() || new.is_array()) {
+            return Ok(serde_json::to_string(new)?);
+        }
+        let mut buf = Vec::new();
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(self.indent_unit.as_bytes());
+        let mut serializer = serde_json::Serializer::with_formatter(&mut buf, formatter);
+        new.serialize(&mut serializer)?;
+        let text = String::from_utf8(buf)?;
+        Ok(text.replace('\n', &format!("\n{}", line_indent(self.source, pos))))
+    }
+}
+
+/// Splits a separator into what is on the line of the item before it (`, // comment`) and what
+/// is on the next lines, which goes with the item after it.
+fn split_separator(separator: &str) -> (&str, &str) {
+    match separator.find('\n') {
+        Some(newline) => separator.split_at(newline),
+        None => ("", separator),
+    }
+}
+
+/// The whitespace at the start of the line that contains `pos`.
+fn line_indent(source: &str, pos: usize) -> &str {
+    let line_start = source[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let line = &source[line_start..];
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+/// Guesses the indentation of the document from its first nested value.
+fn detect_indent_unit(source: &str, root: &Node) -> String {
+    let first_child = match &root.kind {
+        NodeKind::Object(members) => members.first().map(|member| member.key_span.start),
+        NodeKind::Array(elements) => elements.first().map(|element| element.span.start),
+        NodeKind::Scalar => None,
+    };
+    match first_child {
+        Some(pos) if !line_indent(source, pos).is_empty() => {
+            let root_indent = line_indent(source, root.span.start);
+            line_indent(source, pos)
+                .strip_prefix(root_indent)
+                .unwrap_or("  ")
+                .to_string()
+        }
+        _ => String::from("  "),
+    }
+}
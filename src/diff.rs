@@ -0,0 +1,174 @@
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+
+/// A step of the path to a changed node.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// One change between two documents. Applied in order, the changes turn the first document into
+/// the second one: Array indices refer to the Array as left by the previous changes.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Add(Vec<PathSegment>, Value),
+    Remove(Vec<PathSegment>),
+    Replace(Vec<PathSegment>, Value),
+}
+
+/// Lists the changes that turn `old` into `new`. This is a structural diff of the two
+/// documents, not a record of the edits the commands made: keys that jed renamed (`S`) show up
+/// as a removed key and an added one, and the changes to an Array are found by comparing its
+/// elements (removed elements when the ones left are in order, element by element otherwise),
+/// so the same script can give different patches on different documents.
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_into(old, new, &mut Vec::new(), &mut changes);
+    changes
+}
+
+fn diff_into(old: &Value, new: &Value, path: &mut Vec<PathSegment>, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            diff_objects(old_map, new_map, path, changes)
+        }
+        (Value::Array(old_vec), Value::Array(new_vec)) => {
+            diff_arrays(old_vec, new_vec, path, changes)
+        }
+        _ if old == new => (),
+        _ => changes.push(Change::Replace(path.clone(), new.clone())),
+    }
+}
+
+fn diff_objects(
+    old: &Map<String, Value>,
+    new: &Map<String, Value>,
+    path: &mut Vec<PathSegment>,
+    changes: &mut Vec<Change>,
+) {
+    for (k, old_value) in old {
+        path.push(PathSegment::Key(k.clone()));
+        match new.get(k) {
+            Some(new_value) => diff_into(old_value, new_value, path, changes),
+            None => changes.push(Change::Remove(path.clone())),
+        }
+        path.pop();
+    }
+    for (k, new_value) in new {
+        if !old.contains_key(k) {
+            path.push(PathSegment::Key(k.clone()));
+            changes.push(Change::Add(path.clone(), new_value.clone()));
+            path.pop();
+        }
+    }
+}
+
+fn diff_arrays(
+    old: &[Value],
+    new: &[Value],
+    path: &mut Vec<PathSegment>,
+    changes: &mut Vec<Change>,
+) {
+    // Elements were removed: find the ones that are left, if nothing else changed
+    if new.len() < old.len() {
+        let mut kept = HashSet::new();
+        let mut i = 0;
+        for value in new {
+            while i < old.len() && old[i] != *value {
+                i += 1;
+            }
+            if i == old.len() {
+                break;
+            }
+            kept.insert(i);
+            i += 1;
+        }
+        if kept.len() == new.len() {
+            for i in (0..old.len()).rev().filter(|i| !kept.contains(i)) {
+                path.push(PathSegment::Index(i));
+                changes.push(Change::Remove(path.clone()));
+                path.pop();
+            }
+            return;
+        }
+    }
+    for (i, (old_value, new_value)) in old.iter().zip(new).enumerate() {
+        path.push(PathSegment::Index(i));
+        diff_into(old_value, new_value, path, changes);
+        path.pop();
+    }
+    for i in (new.len()..old.len()).rev() {
+        path.push(PathSegment::Index(i));
+        changes.push(Change::Remove(path.clone()));
+        path.pop();
+    }
+    for (i, new_value) in new.iter().enumerate().skip(old.len()) {
+        path.push(PathSegment::Index(i));
+        changes.push(Change::Add(path.clone(), new_value.clone()));
+        path.pop();
+    }
+}
+
+/// Writes a path as a JSON Pointer (RFC 6901), e.g. `/authors/0/name`.
+pub fn to_pointer(path: &[PathSegment]) -> String {
+    let mut pointer = String::new();
+    for segment in path {
+        pointer.push('/');
+        match segment {
+            PathSegment::Key(k) => pointer.push_str(&k.replace('~', "~0").replace('/', "~1")),
+            PathSegment::Index(i) => pointer.push_str(&i.to_string()),
+        }
+    }
+    pointer
+}
+
+/// Writes the changes as a JSON Patch (RFC 6902).
+pub fn to_json_patch(changes: &[Change]) -> Value {
+    Value::Array(
+        changes
+            .iter()
+            .map(|change| match change {
+                Change::Add(path, value) => {
+                    json!({"op": "add", "path": to_pointer(path), "value": value})
+                }
+                Change::Remove(path) => json!({"op": "remove", "path": to_pointer(path)}),
+                Change::Replace(path, value) => {
+                    json!({"op": "replace", "path": to_pointer(path), "value": value})
+                }
+            })
+            .collect(),
+    )
+}
+
+/// A unified diff of two texts, as printed by `diff -u`.
+pub fn unified(before: &str, after: &str, file_name: &str) -> String {
+    similar::TextDiff::from_lines(before, after)
+        .unified_diff()
+        .header(&format!("a/{file_name}"), &format!("b/{file_name}"))
+        .to_string()
+}
+
+/// One line per change between `old` and `new`: `+ path: value` for what was added,
+/// `- path: value` for what was removed and `~ path: old -> new` for what was replaced.
+pub fn paths(old: &Value, new: &Value) -> String {
+    let mut out = String::new();
+    for change in diff(old, new) {
+        let line = match change {
+            Change::Add(path, value) => format!("+ {}: {value}", to_pointer(&path)),
+            Change::Remove(path) => {
+                let pointer = to_pointer(&path);
+                let before = old.pointer(&pointer).unwrap_or(&Value::Null);
+                format!("- {pointer}: {before}")
+            }
+            Change::Replace(path, value) => {
+                let pointer = to_pointer(&path);
+                let before = old.pointer(&pointer).unwrap_or(&Value::Null);
+                format!("~ {pointer}: {before} -> {value}")
+            }
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
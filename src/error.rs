@@ -0,0 +1,44 @@
+use std::fmt;
+
+/// Why jed failed: the script doesn't compile, or a command failed while running on a document.
+///
+/// The message says what went wrong, and `source` what caused it, e.g. the error of the regex
+/// crate for an invalid pattern.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    inner: anyhow::Error,
+}
+
+/// The kinds of [`Error`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The script doesn't compile: a command that doesn't parse, an invalid regex, a label
+    /// defined twice or one that `b`, `t` or `T` can't find, or a file of `r` that can't be read.
+    Compile,
+    /// A command failed while running on a document.
+    Run,
+}
+
+impl Error {
+    pub(crate) fn new(kind: ErrorKind, inner: anyhow::Error) -> Error {
+        Error { kind, inner }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.inner, f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
+}
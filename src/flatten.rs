@@ -0,0 +1,189 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+use std::fmt::Write;
+
+/// Name of the root of the document in the flattened format, as in gron.
+const ROOT: &str = "json";
+
+/// How many missing elements an index can skip past the end of an Array: the lines can be
+/// filtered, but a huge index would allocate the memory of all the elements before it.
+const MAX_INDEX_GAP: usize = 1_000_000;
+
+/// A step of a path in the flattened format: `.key`, `["key"]` or `[0]`.
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Writes every node of the document as a `path = value;` line, e.g. `json.authors[0].name = "camilo";`.
+/// Objects and Arrays get a line of their own (`json.authors = [];`) so that empty ones and the
+/// order of the keys survive `unflatten`.
+pub fn flatten(v: &Value) -> Result<String> {
+    let mut out = String::new();
+    flatten_into(v, &mut String::from(ROOT), &mut out)?;
+    Ok(out)
+}
+
+fn flatten_into(v: &Value, path: &mut String, out: &mut String) -> Result<()> {
+    let value = match v {
+        Value::Object(_) => String::from("{}"),
+        Value::Array(_) => String::from("[]"),
+        _ => serde_json::to_string(v)?,
+    };
+    writeln!(out, "{path} = {value};")?;
+    let path_len = path.len();
+    match v {
+        Value::Object(map) => {
+            for (k, value) in map {
+                if is_identifier(k) {
+                    write!(path, ".{k}")?;
+                } else {
+                    write!(path, "[{}]", serde_json::to_string(k)?)?;
+                }
+                flatten_into(value, path, out)?;
+                path.truncate(path_len);
+            }
+        }
+        Value::Array(vec) => {
+            for (i, value) in vec.iter().enumerate() {
+                write!(path, "[{i}]")?;
+                flatten_into(value, path, out)?;
+                path.truncate(path_len);
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Keys that can be written `.key` instead of `["key"]`.
+fn is_identifier(k: &str) -> bool {
+    let mut chars = k.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+/// Reads the `path = value;` lines written by `flatten` back into a document. The lines can
+/// come in any order and be filtered (by grep for example): the missing Objects and Arrays are
+/// created on the way.
+pub fn unflatten(text: &str) -> Result<Value> {
+    let mut root = Value::Null;
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        parse_line(line)
+            .and_then(|(path, value)| assign(&mut root, &path, value))
+            .with_context(|| format!("Could not parse line {} of the flattened input", i + 1))?;
+    }
+    Ok(root)
+}
+
+fn parse_line(line: &str) -> Result<(Vec<Segment>, Value)> {
+    let mut rest = line
+        .trim()
+        .strip_prefix(ROOT)
+        .with_context(|| format!("The path doesn't start with {ROOT}: {line}"))?;
+    let mut path = Vec::new();
+    loop {
+        if let Some(after_dot) = rest.strip_prefix('.') {
+            let end = after_dot
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(after_dot.len());
+            if end == 0 {
+                bail!("Missing key after '.': {line}");
+            }
+            path.push(Segment::Key(after_dot[..end].to_string()));
+            rest = &after_dot[end..];
+        } else if let Some(after_bracket) = rest.strip_prefix('[') {
+            let end =
+                closing_bracket(after_bracket).with_context(|| format!("Missing ']': {line}"))?;
+            let inside = &after_bracket[..end];
+            if inside.starts_with('"') {
+                path.push(Segment::Key(
+                    serde_json::from_str(inside)
+                        .with_context(|| format!("Invalid key {inside}"))?,
+                ));
+            } else {
+                path.push(Segment::Index(
+                    inside
+                        .parse()
+                        .with_context(|| format!("Invalid index {inside}"))?,
+                ));
+            }
+            rest = &after_bracket[end + 1..];
+        } else {
+            break;
+        }
+    }
+    let value = rest
+        .trim_start()
+        .strip_prefix('=')
+        .with_context(|| format!("Missing '=': {line}"))?
+        .trim()
+        .trim_end_matches(';');
+    let value = serde_json::from_str(value).with_context(|| format!("Invalid value {value}"))?;
+    Ok((path, value))
+}
+
+/// Position of the `]` that closes a `[`, skipping the ones inside a quoted key.
+fn closing_bracket(text: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ']' if !in_string => return Some(i),
+            _ => (),
+        }
+    }
+    None
+}
+
+fn assign(node: &mut Value, path: &[Segment], value: Value) -> Result<()> {
+    let Some((segment, rest)) = path.split_first() else {
+        // `json.a = {};` doesn't erase the keys of `a` read before it
+        let keep = match (&*node, &value) {
+            (Value::Object(_), Value::Object(new)) => new.is_empty(),
+            (Value::Array(_), Value::Array(new)) => new.is_empty(),
+            _ => false,
+        };
+        if !keep {
+            *node = value;
+        }
+        return Ok(());
+    };
+    match segment {
+        Segment::Key(k) => {
+            if !node.is_object() {
+                *node = Value::Object(Map::new());
+            }
+            if let Value::Object(map) = node {
+                assign(map.entry(k.clone()).or_insert(Value::Null), rest, value)?;
+            }
+        }
+        Segment::Index(i) => {
+            if !node.is_array() {
+                *node = Value::Array(Vec::new());
+            }
+            if let Value::Array(vec) = node {
+                if vec.len() <= *i {
+                    let len = i
+                        .checked_add(1)
+                        .filter(|len| len - vec.len() <= MAX_INDEX_GAP)
+                        .with_context(|| {
+                            format!(
+                                "Index {i} is too far past the end of an Array of {}",
+                                vec.len()
+                            )
+                        })?;
+                    vec.resize(len, Value::Null);
+                }
+                assign(&mut vec[*i], rest, value)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,553 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+use crate::{cst, table};
+
+/// The formats jed can read and write. Whatever the format, the commands work on the
+/// `serde_json::Value` model.
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    Json,
+    /// JSON with comments and trailing commas.
+    Jsonc,
+    Json5,
+    Yaml,
+    Toml,
+    Csv,
+    /// Tab separated values.
+    Tsv,
+    Msgpack,
+    Cbor,
+}
+
+impl Format {
+    /// Guesses the format of a file from its extension.
+    pub fn from_extension(file_name: &str) -> Option<Format> {
+        let extension = std::path::Path::new(file_name).extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "jsonc" => Some(Format::Jsonc),
+            "json5" => Some(Format::Json5),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            "csv" => Some(Format::Csv),
+            "tsv" => Some(Format::Tsv),
+            "msgpack" | "mpk" => Some(Format::Msgpack),
+            "cbor" => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    /// JSON, JSONC and JSON5 are all written as JSON.
+    pub fn is_json(self) -> bool {
+        matches!(self, Format::Json | Format::Jsonc | Format::Json5)
+    }
+
+    /// MessagePack and CBOR are read and written as bytes, not text.
+    pub fn is_binary(self) -> bool {
+        matches!(self, Format::Msgpack | Format::Cbor)
+    }
+}
+
+/// Reads the documents of the input. A YAML stream can hold several documents separated by `---`.
+/// `infer_types` reads the cells of a CSV or TSV table that look like Numbers and Booleans as
+/// such, instead of as Strings.
+pub fn parse_documents(text: &str, format: Format, infer_types: bool) -> Result<Vec<Value>> {
+    match format {
+        Format::Json => Ok(vec![
+            serde_json::from_str(text).context("Could not parse file into JSON")?
+        ]),
+        Format::Jsonc => {
+            let root = cst::parse(text, true).context("Could not parse file into JSONC")?;
+            Ok(vec![cst::to_value(text, &root)?])
+        }
+        Format::Json5 => Ok(vec![
+            json5::from_str(text).context("Could not parse file into JSON5")?
+        ]),
+        Format::Yaml => serde_norway::Deserializer::from_str(text)
+            .map(|document| Value::deserialize(document).context("Could not parse file into YAML"))
+            .collect(),
+        Format::Toml => {
+            let table: toml::Table =
+                toml::from_str(text).context("Could not parse file into TOML")?;
+            Ok(vec![from_toml(toml::Value::Table(table))])
+        }
+        Format::Csv => Ok(vec![table::from_csv(text, b',', infer_types)?]),
+        Format::Tsv => Ok(vec![table::from_csv(text, b'\t', infer_types)?]),
+        Format::Msgpack | Format::Cbor => {
+            anyhow::bail!("MessagePack and CBOR are binary formats, see parse_binary_documents")
+        }
+    }
+}
+
+/// Reads the documents of a MessagePack or CBOR input: one, or several written one after the
+/// other. Byte strings, which JSON doesn't have, are read as base64 Strings.
+pub fn parse_binary_documents(mut bytes: &[u8], format: Format) -> Result<Vec<Value>> {
+    let mut documents = Vec::new();
+    loop {
+        documents.push(match format {
+            Format::Cbor => {
+                let v =
+                    ciborium::from_reader(&mut bytes).context("Could not parse file into CBOR")?;
+                from_cbor(v).context("Could not parse file into CBOR")?
+            }
+            _ => {
+                let v: rmpv::Value = rmp_serde::from_read(&mut bytes)
+                    .context("Could not parse file into MessagePack")?;
+                from_msgpack(v).context("Could not parse file into MessagePack")?
+            }
+        });
+        if bytes.is_empty() {
+            return Ok(documents);
+        }
+    }
+}
+
+fn from_bytes(bytes: &[u8]) -> Value {
+    use base64::Engine;
+    Value::String(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+fn from_float(f: f64) -> Value {
+    serde_json::Number::from_f64(f).map_or(Value::Null, Value::Number)
+}
+
+/// Converts MessagePack to the Value model: binary data and extension types become base64
+/// Strings, and the keys that aren't Strings are written as JSON text.
+fn from_msgpack(v: rmpv::Value) -> Result<Value> {
+    Ok(match v {
+        rmpv::Value::Nil => Value::Null,
+        rmpv::Value::Boolean(b) => Value::Bool(b),
+        rmpv::Value::Integer(i) => match (i.as_i64(), i.as_u64()) {
+            (Some(i), _) => Value::from(i),
+            (_, Some(u)) => Value::from(u),
+            _ => bail!("Invalid integer {i}"),
+        },
+        rmpv::Value::F32(f) => from_float(f.into()),
+        rmpv::Value::F64(f) => from_float(f),
+        rmpv::Value::String(s) => match s.into_str() {
+            Some(s) => Value::String(s),
+            None => bail!("A String is not valid UTF-8"),
+        },
+        rmpv::Value::Binary(bytes) | rmpv::Value::Ext(_, bytes) => from_bytes(&bytes),
+        rmpv::Value::Array(array) => {
+            Value::Array(array.into_iter().map(from_msgpack).collect::<Result<_>>()?)
+        }
+        rmpv::Value::Map(map) => Value::Object(
+            map.into_iter()
+                .map(|(k, v)| Ok((to_key(from_msgpack(k)?), from_msgpack(v)?)))
+                .collect::<Result<_>>()?,
+        ),
+    })
+}
+
+/// Converts CBOR to the Value model: byte strings become base64 Strings, tags are dropped (a
+/// tagged date is its String), and the keys that aren't Strings are written as JSON text.
+fn from_cbor(v: ciborium::Value) -> Result<Value> {
+    Ok(match v {
+        ciborium::Value::Null => Value::Null,
+        ciborium::Value::Bool(b) => Value::Bool(b),
+        ciborium::Value::Integer(i) => {
+            serde_json::from_str(&i128::from(i).to_string()).context("Invalid integer")?
+        }
+        ciborium::Value::Float(f) => from_float(f),
+        ciborium::Value::Text(s) => Value::String(s),
+        ciborium::Value::Bytes(bytes) => from_bytes(&bytes),
+        ciborium::Value::Tag(_, v) => from_cbor(*v)?,
+        ciborium::Value::Array(array) => {
+            Value::Array(array.into_iter().map(from_cbor).collect::<Result<_>>()?)
+        }
+        ciborium::Value::Map(map) => Value::Object(
+            map.into_iter()
+                .map(|(k, v)| Ok((to_key(from_cbor(k)?), from_cbor(v)?)))
+                .collect::<Result<_>>()?,
+        ),
+        _ => bail!("Unsupported CBOR value"),
+    })
+}
+
+fn to_key(k: Value) -> String {
+    match k {
+        Value::String(s) => s,
+        k => k.to_string(),
+    }
+}
+
+/// What a base64 String held in the MessagePack or CBOR input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binary {
+    /// MessagePack binary data or a CBOR byte string.
+    Bytes,
+    /// A MessagePack extension type.
+    Ext(i8),
+}
+
+/// The paths (e.g. `/files/0/data`) of the binary data of the documents of a MessagePack or CBOR
+/// input, which `parse_binary_documents` turns into base64 Strings.
+pub fn binary_paths(mut bytes: &[u8], format: Format) -> Result<HashMap<String, Binary>> {
+    fn visit<T>(
+        path: &mut String,
+        segment: &str,
+        f: impl FnOnce(&mut String) -> Result<T>,
+    ) -> Result<T> {
+        let len = path.len();
+        path.push('/');
+        path.push_str(segment);
+        let result = f(path);
+        path.truncate(len);
+        result
+    }
+    fn collect_msgpack(
+        v: &rmpv::Value,
+        path: &mut String,
+        paths: &mut HashMap<String, Binary>,
+    ) -> Result<()> {
+        match v {
+            rmpv::Value::Binary(_) => {
+                paths.insert(path.clone(), Binary::Bytes);
+            }
+            rmpv::Value::Ext(tag, _) => {
+                paths.insert(path.clone(), Binary::Ext(*tag));
+            }
+            rmpv::Value::Array(array) => {
+                for (i, value) in array.iter().enumerate() {
+                    visit(path, &i.to_string(), |path| {
+                        collect_msgpack(value, path, paths)
+                    })?;
+                }
+            }
+            rmpv::Value::Map(map) => {
+                for (k, value) in map {
+                    let k = to_key(from_msgpack(k.clone())?);
+                    visit(path, &k, |path| collect_msgpack(value, path, paths))?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+    fn collect_cbor(
+        v: &ciborium::Value,
+        path: &mut String,
+        paths: &mut HashMap<String, Binary>,
+    ) -> Result<()> {
+        match v {
+            ciborium::Value::Bytes(_) => {
+                paths.insert(path.clone(), Binary::Bytes);
+            }
+            ciborium::Value::Tag(_, v) => collect_cbor(v, path, paths)?,
+            ciborium::Value::Array(array) => {
+                for (i, value) in array.iter().enumerate() {
+                    visit(path, &i.to_string(), |path| {
+                        collect_cbor(value, path, paths)
+                    })?;
+                }
+            }
+            ciborium::Value::Map(map) => {
+                for (k, value) in map {
+                    let k = to_key(from_cbor(k.clone())?);
+                    visit(path, &k, |path| collect_cbor(value, path, paths))?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+    let mut paths = HashMap::new();
+    while !bytes.is_empty() {
+        match format {
+            Format::Cbor => {
+                let v =
+                    ciborium::from_reader(&mut bytes).context("Could not parse file into CBOR")?;
+                collect_cbor(&v, &mut String::new(), &mut paths)?;
+            }
+            _ => {
+                let v: rmpv::Value = rmp_serde::from_read(&mut bytes)
+                    .context("Could not parse file into MessagePack")?;
+                collect_msgpack(&v, &mut String::new(), &mut paths)?;
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Writes a document as MessagePack or CBOR. The base64 Strings found at the `binary` paths (see
+/// `binary_paths`) go back to binary data, so that the fields a script didn't touch keep their
+/// type.
+pub fn to_bytes(v: &Value, format: Format, binary: &HashMap<String, Binary>) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    match format {
+        Format::Cbor => {
+            let v = to_cbor(v, &mut String::new(), binary);
+            ciborium::into_writer(&v, &mut bytes).context("Could not write CBOR")?;
+        }
+        _ => {
+            let v = to_msgpack(v, &mut String::new(), binary);
+            rmpv::encode::write_value(&mut bytes, &v).context("Could not write MessagePack")?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// The bytes of a String found at a path of `binary`, unless a script made it something other
+/// than base64.
+fn to_binary(s: &str, path: &str, binary: &HashMap<String, Binary>) -> Option<(Binary, Vec<u8>)> {
+    use base64::Engine;
+    let kind = *binary.get(path)?;
+    let bytes = base64::engine::general_purpose::STANDARD.decode(s).ok()?;
+    Some((kind, bytes))
+}
+
+fn to_msgpack(v: &Value, path: &mut String, binary: &HashMap<String, Binary>) -> rmpv::Value {
+    match v {
+        Value::Null => rmpv::Value::Nil,
+        Value::Bool(b) => rmpv::Value::Boolean(*b),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => rmpv::Value::from(i),
+            (_, Some(u)) => rmpv::Value::from(u),
+            _ => rmpv::Value::F64(to_f64(n)),
+        },
+        Value::String(s) => match to_binary(s, path, binary) {
+            Some((Binary::Bytes, bytes)) => rmpv::Value::Binary(bytes),
+            Some((Binary::Ext(tag), bytes)) => rmpv::Value::Ext(tag, bytes),
+            None => rmpv::Value::from(s.as_str()),
+        },
+        Value::Array(vec) => {
+            let mut array = Vec::new();
+            for (i, value) in vec.iter().enumerate() {
+                let len = path.len();
+                path.push_str(&format!("/{i}"));
+                array.push(to_msgpack(value, path, binary));
+                path.truncate(len);
+            }
+            rmpv::Value::Array(array)
+        }
+        Value::Object(map) => {
+            let mut entries = Vec::new();
+            for (k, value) in map {
+                let len = path.len();
+                path.push('/');
+                path.push_str(k);
+                entries.push((
+                    rmpv::Value::from(k.as_str()),
+                    to_msgpack(value, path, binary),
+                ));
+                path.truncate(len);
+            }
+            rmpv::Value::Map(entries)
+        }
+    }
+}
+
+fn to_cbor(v: &Value, path: &mut String, binary: &HashMap<String, Binary>) -> ciborium::Value {
+    match v {
+        Value::Null => ciborium::Value::Null,
+        Value::Bool(b) => ciborium::Value::Bool(*b),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => ciborium::Value::Integer(i.into()),
+            (_, Some(u)) => ciborium::Value::Integer(u.into()),
+            _ => ciborium::Value::Float(to_f64(n)),
+        },
+        // CBOR has no extension types, they are written as byte strings
+        Value::String(s) => match to_binary(s, path, binary) {
+            Some((_, bytes)) => ciborium::Value::Bytes(bytes),
+            None => ciborium::Value::Text(s.clone()),
+        },
+        Value::Array(vec) => {
+            let mut array = Vec::new();
+            for (i, value) in vec.iter().enumerate() {
+                let len = path.len();
+                path.push_str(&format!("/{i}"));
+                array.push(to_cbor(value, path, binary));
+                path.truncate(len);
+            }
+            ciborium::Value::Array(array)
+        }
+        Value::Object(map) => {
+            let mut entries = Vec::new();
+            for (k, value) in map {
+                let len = path.len();
+                path.push('/');
+                path.push_str(k);
+                entries.push((
+                    ciborium::Value::Text(k.clone()),
+                    to_cbor(value, path, binary),
+                ));
+                path.truncate(len);
+            }
+            ciborium::Value::Map(entries)
+        }
+    }
+}
+
+/// Writes a document in a format other than JSON, which has its own output options. `columns`
+/// picks and orders the columns of a CSV or TSV table. `datetimes` are the paths that held a
+/// date or time in the TOML input (see `toml_datetimes`): only the Strings found there are
+/// written back as TOML dates and times.
+pub fn to_text(
+    v: &Value,
+    format: Format,
+    columns: &[String],
+    datetimes: &HashSet<String>,
+) -> Result<String> {
+    match format {
+        Format::Json | Format::Jsonc | Format::Json5 => Ok(serde_json::to_string_pretty(v)? + "\n"),
+        Format::Yaml => serde_norway::to_string(&Plain(v)).context("Could not write YAML"),
+        Format::Toml => {
+            let toml::Value::Table(table) = to_toml(v, &mut String::new(), datetimes)? else {
+                anyhow::bail!("Could not write TOML: a TOML document must be a Table");
+            };
+            toml::to_string(&table).context("Could not write TOML")
+        }
+        Format::Csv => table::to_csv(v, b',', columns),
+        Format::Tsv => table::to_csv(v, b'\t', columns),
+        Format::Msgpack | Format::Cbor => {
+            anyhow::bail!("MessagePack and CBOR are binary formats, see to_bytes")
+        }
+    }
+}
+
+/// What goes between two documents of the output.
+pub fn document_separator(format: Format) -> &'static str {
+    match format {
+        Format::Json
+        | Format::Jsonc
+        | Format::Json5
+        | Format::Toml
+        | Format::Csv
+        | Format::Tsv
+        | Format::Msgpack
+        | Format::Cbor => "",
+        Format::Yaml => "---\n",
+    }
+}
+
+/// Serializes a Value with its Numbers as plain numbers: serde_json keeps them as text
+/// (`arbitrary_precision`), which only its own serializer understands.
+struct Plain<'a>(&'a Value);
+
+impl Serialize for Plain<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    serializer.serialize_i64(i)
+                } else if let Some(u) = n.as_u64() {
+                    serializer.serialize_u64(u)
+                } else {
+                    serializer.serialize_f64(to_f64(n))
+                }
+            }
+            Value::Array(vec) => serializer.collect_seq(vec.iter().map(Plain)),
+            Value::Object(map) => serializer.collect_map(map.iter().map(|(k, v)| (k, Plain(v)))),
+            _ => self.0.serialize(serializer),
+        }
+    }
+}
+
+/// The closest float to a Number, infinite if it is too big (`as_f64` gives up on those).
+fn to_f64(n: &serde_json::Number) -> f64 {
+    n.to_string().parse().unwrap_or(f64::NAN)
+}
+
+/// Converts TOML to the Value model: dates and times become Strings, and so do the Floats that
+/// JSON can't represent (`nan`, `inf`).
+fn from_toml(v: toml::Value) -> Value {
+    match v {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => {
+            serde_json::Number::from_f64(f).map_or(Value::String(f.to_string()), Value::Number)
+        }
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(array) => Value::Array(array.into_iter().map(from_toml).collect()),
+        toml::Value::Table(table) => {
+            Value::Object(table.into_iter().map(|(k, v)| (k, from_toml(v))).collect())
+        }
+    }
+}
+
+/// The paths (e.g. `/package/released`) of the dates and times of a TOML document, which
+/// `from_toml` turns into Strings.
+pub fn toml_datetimes(text: &str) -> Result<HashSet<String>> {
+    fn collect(v: &toml::Value, path: &mut String, datetimes: &mut HashSet<String>) {
+        let mut visit = |segment: &str, child| {
+            let len = path.len();
+            path.push('/');
+            path.push_str(segment);
+            collect(child, path, datetimes);
+            path.truncate(len);
+        };
+        match v {
+            toml::Value::Datetime(_) => {
+                datetimes.insert(path.clone());
+            }
+            toml::Value::Array(array) => {
+                for (i, value) in array.iter().enumerate() {
+                    visit(&i.to_string(), value);
+                }
+            }
+            toml::Value::Table(table) => {
+                for (k, value) in table {
+                    visit(k, value);
+                }
+            }
+            _ => (),
+        }
+    }
+    let table: toml::Table = toml::from_str(text).context("Could not parse file into TOML")?;
+    let mut datetimes = HashSet::new();
+    collect(
+        &toml::Value::Table(table),
+        &mut String::new(),
+        &mut datetimes,
+    );
+    Ok(datetimes)
+}
+
+/// Converts a Value to TOML, the Strings found where the input had a date or time going back to
+/// dates and times. Fails on `null`, which TOML doesn't have, and on integers too big for TOML,
+/// telling where they were found.
+fn to_toml(v: &Value, path: &mut String, datetimes: &HashSet<String>) -> Result<toml::Value> {
+    Ok(match v {
+        Value::Null => {
+            anyhow::bail!("Could not write TOML: TOML has no null, found one at {path:?}")
+        }
+        Value::Bool(b) => toml::Value::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => toml::Value::Integer(i),
+            None if n.to_string().contains(['.', 'e', 'E']) => toml::Value::Float(to_f64(n)),
+            None => anyhow::bail!(
+                "Could not write TOML: {n} at {path:?} doesn't fit in a TOML integer (64 bits)"
+            ),
+        },
+        Value::String(s) => match s.parse::<toml::value::Datetime>() {
+            Ok(datetime) if datetimes.contains(path.as_str()) => toml::Value::Datetime(datetime),
+            _ => toml::Value::String(s.clone()),
+        },
+        Value::Array(vec) => {
+            let mut array = toml::value::Array::new();
+            for (i, value) in vec.iter().enumerate() {
+                let len = path.len();
+                path.push_str(&format!("/{i}"));
+                array.push(to_toml(value, path, datetimes)?);
+                path.truncate(len);
+            }
+            toml::Value::Array(array)
+        }
+        Value::Object(map) => {
+            let mut table = toml::Table::new();
+            for (k, value) in map {
+                let len = path.len();
+                path.push('/');
+                path.push_str(k);
+                table.insert(k.clone(), to_toml(value, path, datetimes)?);
+                path.truncate(len);
+            }
+            toml::Value::Table(table)
+        }
+    })
+}
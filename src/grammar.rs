@@ -1,48 +1,172 @@
 use anyhow::{Context, Result};
+use pest::iterators::Pair;
 use pest::Parser as _;
 use pest_derive::Parser;
 use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
 
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 pub struct SedParser;
 
+/// The indices `begin` to `end` (both included) of an Array, written `begin,end`.
 #[derive(Clone)]
 pub struct ArrayRange {
     pub begin: usize,
     pub end: usize,
 }
+/// A step of the address of a command. The steps of an address are separated by `.`: `/a/./b/`
+/// is the `b` keys of the `a` keys.
 #[derive(Clone)]
 pub enum RangeType {
+    /// `/regex/`: the values of the keys that match.
     Key(Regex),
+    /// `begin,end`: the elements of an Array in that range.
     Array(ArrayRange),
+    /// `:/regex/`: the Strings that match.
     Value(Regex),
 }
+/// A command, run on the nodes its address matches (on the whole document without one).
+#[non_exhaustive]
 pub enum JedCommand {
+    /// `s/regex/replacement/`: replaces in the String values.
     Substitute(SubstituteParams),
+    /// `S/regex/replacement/`: replaces in the keys.
     SubstituteKeys(SubstituteParams),
+    /// `p`: keeps only what matches.
     Print,
+    /// `d`: removes what matches.
     Delete,
-    Other(()),
+    /// `r file` and `R file`: merges a JSON file into what matches.
+    Read(ReadParams),
+    /// `h`: copies what matches to the hold space.
+    Hold,
+    /// `H`: appends what matches to the hold space.
+    HoldAppend,
+    /// `g`: replaces what matches with the hold space.
+    Get,
+    /// `G`: appends the hold space to what matches.
+    GetAppend,
+    /// `x`: exchanges what matches with the hold space.
+    Exchange,
+    /// `:label`, where `b`, `t` and `T` jump to.
+    Label(String),
+    /// `b label`: jumps if the address matches.
+    Branch(BranchParams),
+    /// `t label`: jumps if a substitution was made since the start or the last `t`/`T`.
+    BranchIfSubstituted(BranchParams),
+    /// `T label`: jumps unless a substitution was made since the start or the last `t`/`T`.
+    BranchUnlessSubstituted(BranchParams),
+    /// `q` and `Q`: stops the script.
+    Quit(QuitParams),
 }
 pub struct SubstituteParams {
     pub pattern: Regex,
     pub replacement: String,
 }
+pub struct ReadParams {
+    pub file_name: String,
+    /// `R` merges the nested Objects too, `r` only the top-level keys.
+    pub deep: bool,
+    /// The JSON in the file, read by `compile_program` so that every document gets the same.
+    pub(crate) contents: Value,
+}
+pub struct BranchParams {
+    /// The label to jump to, the end of the program if there is none.
+    pub label: Option<String>,
+    /// Index of the command to jump to, resolved by `compile_program`.
+    pub(crate) target: usize,
+}
+#[derive(Clone, Copy)]
+pub struct QuitParams {
+    /// The exit status, 0 to 255.
+    pub exit_code: u8,
+    /// `q` prints the document before exiting, `Q` doesn't.
+    pub print: bool,
+}
 
-pub fn parse_grammar(input: &String) -> Result<(Vec<RangeType>, JedCommand)> {
-    let mut stack = Vec::new();
-    let parsed = SedParser::parse(Rule::substitute, input)
+/// A compiled jed script: the commands of every expression, in order, with the targets of
+/// the branch commands resolved. See [`Program::compile`].
+pub struct Program {
+    pub(crate) commands: Vec<(Vec<RangeType>, JedCommand)>,
+}
+
+/// Parses every expression, resolves the labels used by `b`, `t` and `T` and reads the files of
+/// `r` and `R`.
+pub fn compile_program(inputs: &[String]) -> Result<Program> {
+    let mut commands = Vec::new();
+    for input in inputs {
+        commands.extend(parse_program(input)?);
+    }
+    let mut labels = HashMap::new();
+    for (i, (_, command)) in commands.iter().enumerate() {
+        if let JedCommand::Label(label) = command {
+            if labels.insert(label.clone(), i).is_some() {
+                anyhow::bail!("Label {label} is defined more than once");
+            }
+        }
+    }
+    let end = commands.len();
+    for (_, command) in commands.iter_mut() {
+        match command {
+            JedCommand::Branch(params)
+            | JedCommand::BranchIfSubstituted(params)
+            | JedCommand::BranchUnlessSubstituted(params) => {
+                params.target = match &params.label {
+                    Some(label) => *labels
+                        .get(label)
+                        .with_context(|| format!("Can't find label for jump to {label}"))?,
+                    None => end,
+                };
+            }
+            JedCommand::Read(params) => params.contents = read_json_file(&params.file_name)?,
+            _ => (),
+        }
+    }
+    Ok(Program { commands })
+}
+
+/// Reads the JSON file of `r` and `R`.
+fn read_json_file(file_name: &str) -> Result<Value> {
+    let contents = std::fs::read_to_string(file_name)
+        .with_context(|| format!("Could not read file {file_name}"))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Could not parse {file_name} into JSON"))
+}
+
+/// Parses a jed script: one or more commands separated by `;`, e.g. `/id/ h; /meta/ G`.
+pub fn parse_program(input: &String) -> Result<Vec<(Vec<RangeType>, JedCommand)>> {
+    let parsed = SedParser::parse(Rule::program, input)
         .with_context(|| format!("Parsing the jed command failed: {input}"))?;
-    let mut pattern = Regex::new("")?;
-    let mut replacement = String::from("");
-    let mut sed_command = ' ';
+    let mut program = Vec::new();
     for pair in parsed
         .into_iter()
         .next()
         .context("Parsing the jed command failed")?
         .into_inner()
     {
+        match pair.as_rule() {
+            Rule::substitute => program.push(parse_command(pair)?),
+            Rule::label_definition => {
+                let label = pair.as_str().trim_start_matches(':').to_string();
+                program.push((Vec::new(), JedCommand::Label(label)));
+            }
+            _ => (),
+        }
+    }
+    Ok(program)
+}
+
+fn parse_command(command: Pair<Rule>) -> Result<(Vec<RangeType>, JedCommand)> {
+    let mut stack = Vec::new();
+    let mut pattern = Regex::new("")?;
+    let mut replacement = String::from("");
+    let mut sed_command = ' ';
+    let mut file_name = String::from("");
+    let mut label = None;
+    let mut exit_code = 0;
+    for pair in command.into_inner() {
         match pair.as_rule() {
             Rule::range_regex => {
                 for inner_pair in pair.into_inner() {
@@ -87,10 +211,67 @@ pub fn parse_grammar(input: &String) -> Result<(Vec<RangeType>, JedCommand)> {
                     .next()
                     .context("Failed to parse the Jed command")?;
             }
-            Rule::pattern => {
+            Rule::pattern | Rule::open_pattern => {
                 pattern = Regex::new(pair.as_str()).context("Parsing the search pattern failed")?
             }
-            Rule::replacement => replacement = pair.as_str().to_string(),
+            Rule::replacement | Rule::open_replacement => replacement = pair.as_str().to_string(),
+            // Every match is replaced, `g` only says so
+            Rule::flags if !matches!(pair.as_str(), "" | "g") => {
+                anyhow::bail!(
+                    "Unknown substitution flags {}: only g is supported",
+                    pair.as_str()
+                );
+            }
+            Rule::read_command => {
+                for inner_pair in pair.into_inner() {
+                    match inner_pair.as_rule() {
+                        Rule::read_command_name => {
+                            sed_command = inner_pair
+                                .as_str()
+                                .chars()
+                                .next()
+                                .context("Failed to parse the Jed command")?;
+                        }
+                        Rule::file_name => file_name = inner_pair.as_str().to_string(),
+                        _ => (),
+                    }
+                }
+            }
+            Rule::branch_command => {
+                for inner_pair in pair.into_inner() {
+                    match inner_pair.as_rule() {
+                        Rule::branch_command_name => {
+                            sed_command = inner_pair
+                                .as_str()
+                                .chars()
+                                .next()
+                                .context("Failed to parse the Jed command")?;
+                        }
+                        Rule::label => label = Some(inner_pair.as_str().to_string()),
+                        _ => (),
+                    }
+                }
+            }
+            Rule::quit_command => {
+                for inner_pair in pair.into_inner() {
+                    match inner_pair.as_rule() {
+                        Rule::quit_command_name => {
+                            sed_command = inner_pair
+                                .as_str()
+                                .chars()
+                                .next()
+                                .context("Failed to parse the Jed command")?;
+                        }
+                        Rule::exit_code => {
+                            exit_code = inner_pair
+                                .as_str()
+                                .parse::<u8>()
+                                .context("The exit code must be between 0 and 255")?;
+                        }
+                        _ => (),
+                    }
+                }
+            }
             _ => {}
         }
     }
@@ -119,5 +300,38 @@ pub fn parse_grammar(input: &String) -> Result<(Vec<RangeType>, JedCommand)> {
     if sed_command == 'd' {
         return Ok((stack, JedCommand::Delete));
     }
-    Ok((stack, JedCommand::Other(())))
+    if sed_command == 'r' || sed_command == 'R' {
+        return Ok((
+            stack,
+            JedCommand::Read(ReadParams {
+                file_name,
+                deep: sed_command == 'R',
+                contents: Value::Null,
+            }),
+        ));
+    }
+    match sed_command {
+        'h' => Ok((stack, JedCommand::Hold)),
+        'H' => Ok((stack, JedCommand::HoldAppend)),
+        'g' => Ok((stack, JedCommand::Get)),
+        'G' => Ok((stack, JedCommand::GetAppend)),
+        'x' => Ok((stack, JedCommand::Exchange)),
+        'b' => Ok((stack, JedCommand::Branch(BranchParams { label, target: 0 }))),
+        't' => Ok((
+            stack,
+            JedCommand::BranchIfSubstituted(BranchParams { label, target: 0 }),
+        )),
+        'T' => Ok((
+            stack,
+            JedCommand::BranchUnlessSubstituted(BranchParams { label, target: 0 }),
+        )),
+        'q' | 'Q' => Ok((
+            stack,
+            JedCommand::Quit(QuitParams {
+                exit_code,
+                print: sed_command == 'q',
+            }),
+        )),
+        _ => anyhow::bail!("Unknown jed command: {sed_command}"),
+    }
 }
@@ -0,0 +1,226 @@
+//! jed, a sed for JSON: runs sed-like scripts (`/author/ s/old/new/g`, `/meta/ d`...) on
+//! `serde_json` documents.
+//!
+//! Compile a script once, then apply it to as many documents as needed:
+//!
+//! ```
+//! use jed::Program;
+//! use serde_json::json;
+//!
+//! let program = Program::compile("/email/ s/old.com/new.com/; /password/ d")?;
+//! let user = json!({"email": "camilo@old.com", "password": "1234"});
+//! assert_eq!(program.apply(user)?, json!({"email": "camilo@new.com"}));
+//! # Ok::<(), jed::Error>(())
+//! ```
+//!
+//! A `Program` can be shared between threads. [`Program::run`] also tells how many nodes the
+//! commands matched and whether `q` or `Q` stopped the script.
+
+use anyhow::Result;
+use serde_json::Value;
+
+mod commands;
+mod error;
+pub use error::{Error, ErrorKind};
+mod grammar;
+pub use grammar::{
+    ArrayRange, BranchParams, JedCommand, Program, QuitParams, RangeType, ReadParams,
+    SubstituteParams,
+};
+
+use commands::{
+    delete_on_specified_ranges, exchange_on_specified_ranges, get_on_specified_ranges,
+    hold_on_specified_ranges, matches_any, print_on_specified_ranges, read_on_specified_ranges,
+    substitute_keys, substitute_keys_on_specified_ranges, substitute_values,
+    substitute_values_on_specified_ranges, HoldSpace,
+};
+
+impl Program {
+    /// Compiles a jed script: one or more commands separated by `;`, e.g. `/id/ h; /meta/ G`.
+    pub fn compile(script: &str) -> Result<Program, Error> {
+        Program::compile_all(&[script])
+    }
+
+    /// Compiles the scripts of several `-e` expressions as a single program: a branch can jump
+    /// to a label of another expression.
+    pub fn compile_all(scripts: &[impl AsRef<str>]) -> Result<Program, Error> {
+        let scripts: Vec<String> = scripts.iter().map(|s| s.as_ref().to_string()).collect();
+        grammar::compile_program(&scripts).map_err(|err| Error::new(ErrorKind::Compile, err))
+    }
+
+    /// Runs the program on a document and returns the result. `q` and `Q` stop the program
+    /// and return the document as it is.
+    pub fn apply(&self, v: Value) -> Result<Value, Error> {
+        Ok(self.run(v)?.v)
+    }
+
+    /// Runs the program on a document, telling also how many nodes matched and which `q`/`Q`
+    /// command stopped it.
+    pub fn run(&self, v: Value) -> Result<ProgramOutput, Error> {
+        run_program(self, v).map_err(|err| Error::new(ErrorKind::Run, err))
+    }
+
+    /// The commands of the program in order, each with the steps of its address (empty without
+    /// one). For `--stream` in the jed binary, not part of the stable API.
+    #[doc(hidden)]
+    pub fn commands(&self) -> impl Iterator<Item = (&[RangeType], &JedCommand)> {
+        self.commands
+            .iter()
+            .map(|(stack, command)| (stack.as_slice(), command))
+    }
+}
+
+impl SubstituteParams {
+    /// Replaces the pattern in the Strings of `v`, and in its Numbers, Bools and nulls as
+    /// they are written in JSON, like `s` without an address. Returns the number of values
+    /// changed. For `--stream` in the jed binary, not part of the stable API.
+    #[doc(hidden)]
+    pub fn replace_values(&self, v: Value) -> (Value, usize) {
+        substitute_values(v, &self.pattern, &self.replacement)
+    }
+}
+
+/// The result of running a program on a document.
+pub struct ProgramOutput {
+    /// The document once the program ran on it.
+    pub v: Value,
+    /// Number of nodes matched by the commands (substitutions made, for `s` and `S`).
+    pub matches: usize,
+    /// The `q`/`Q` command that stopped the program, if any.
+    pub quit: Option<QuitParams>,
+}
+
+/// Runs the commands of the program on the document, following the branches.
+fn run_program(program: &Program, mut v: Value) -> Result<ProgramOutput> {
+    let mut hold = HoldSpace::default();
+    let mut matches = 0;
+    // Whether a substitution was made since the start or the last `t`/`T`
+    let mut substituted = false;
+    // Goes up every time a command changes the document. Until then, the addresses of `b`, `t`,
+    // `T` and `q` match as they did, so a loop doesn't walk a document it didn't change
+    let mut version = 0;
+    let mut address_tests = vec![None; program.commands.len()];
+    let mut pc = 0;
+    while let Some((stack, command)) = program.commands.get(pc) {
+        let (command_pc, command_version) = (pc, version);
+        let mut address_matches = |v: &Value| match address_tests[command_pc] {
+            Some((tested_version, matched)) if tested_version == command_version => matched,
+            _ => {
+                let matched = matches_any(v, stack);
+                address_tests[command_pc] = Some((command_version, matched));
+                matched
+            }
+        };
+        pc += 1;
+        let command_matches;
+        match command {
+            JedCommand::Substitute(params) => {
+                if !stack.is_empty() {
+                    (v, command_matches) = substitute_values_on_specified_ranges(
+                        v,
+                        stack,
+                        &params.pattern,
+                        &params.replacement,
+                    );
+                } else {
+                    (v, command_matches) =
+                        substitute_values(v, &params.pattern, &params.replacement);
+                }
+                substituted |= command_matches > 0;
+            }
+            JedCommand::SubstituteKeys(params) => {
+                if !stack.is_empty() {
+                    (v, command_matches) = substitute_keys_on_specified_ranges(
+                        v,
+                        stack,
+                        &params.pattern,
+                        &params.replacement,
+                    );
+                } else {
+                    (v, command_matches) = substitute_keys(v, &params.pattern, &params.replacement);
+                }
+                substituted |= command_matches > 0;
+            }
+            JedCommand::Print => {
+                (v, command_matches) = print_on_specified_ranges(v, stack);
+            }
+            JedCommand::Delete => {
+                (v, command_matches) = delete_on_specified_ranges(v, stack);
+            }
+            JedCommand::Read(params) => {
+                (v, command_matches) =
+                    read_on_specified_ranges(v, stack, &params.contents, params.deep);
+            }
+            JedCommand::Hold => {
+                (v, command_matches) = hold_on_specified_ranges(v, stack, &mut hold, false);
+            }
+            JedCommand::HoldAppend => {
+                (v, command_matches) = hold_on_specified_ranges(v, stack, &mut hold, true);
+            }
+            JedCommand::Get => {
+                (v, command_matches) = get_on_specified_ranges(v, stack, &hold, false);
+            }
+            JedCommand::GetAppend => {
+                (v, command_matches) = get_on_specified_ranges(v, stack, &hold, true);
+            }
+            JedCommand::Exchange => {
+                (v, command_matches) = exchange_on_specified_ranges(v, stack, &mut hold);
+            }
+            JedCommand::Label(_) => command_matches = 0,
+            JedCommand::Branch(params) => {
+                if address_matches(&v) {
+                    pc = params.target;
+                }
+                command_matches = 0;
+            }
+            JedCommand::BranchIfSubstituted(params) => {
+                if substituted && address_matches(&v) {
+                    substituted = false;
+                    pc = params.target;
+                }
+                command_matches = 0;
+            }
+            JedCommand::BranchUnlessSubstituted(params) => {
+                if !substituted && address_matches(&v) {
+                    pc = params.target;
+                } else {
+                    substituted = false;
+                }
+                command_matches = 0;
+            }
+            JedCommand::Quit(params) => {
+                if address_matches(&v) {
+                    return Ok(ProgramOutput {
+                        v,
+                        matches: matches + 1,
+                        quit: Some(*params),
+                    });
+                }
+                command_matches = 0;
+            }
+        }
+        let changed = match command {
+            JedCommand::Substitute(_) | JedCommand::SubstituteKeys(_) => command_matches > 0,
+            JedCommand::Hold
+            | JedCommand::HoldAppend
+            | JedCommand::Label(_)
+            | JedCommand::Branch(_)
+            | JedCommand::BranchIfSubstituted(_)
+            | JedCommand::BranchUnlessSubstituted(_)
+            | JedCommand::Quit(_) => false,
+            _ => true,
+        };
+        if changed {
+            version += 1;
+        }
+        matches += command_matches;
+    }
+    Ok(ProgramOutput {
+        v,
+        matches,
+        quit: None,
+    })
+}
+
+#[cfg(test)]
+mod tests;
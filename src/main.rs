@@ -1,90 +1,639 @@
 use anyhow::{Context, Result};
 use clap::Parser as ClapParser;
-use colored_json::to_colored_json_auto;
 use serde_json::Value;
-use std::io::Read;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Write};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
 #[derive(ClapParser)]
 pub struct Cli {
-    #[clap(short, long, action)]
-    expression: String,
-    input_file: Option<String>,
+    #[clap(
+        short,
+        long,
+        action,
+        required_unless_present_any = ["apply_patch", "merge_patch"]
+    )]
+    expression: Vec<String>,
+    /// The files to run the script on, stdin if none
+    input_files: Vec<String>,
     #[arg(short, long)]
     no_color: bool,
+    /// Exit with status 0 even if no filter matched anything
+    #[arg(long)]
+    no_match_ok: bool,
+    /// Keep the formatting of the input and only rewrite the values that changed
+    #[arg(long, group = "output_mode", conflicts_with_all = ["compact", "indent", "tab", "sort_keys", "ascii", "unflatten"])]
+    preserve_format: bool,
+    /// Print the output on a single line
+    #[arg(short, long)]
+    compact: bool,
+    /// Number of spaces to indent the output with
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 2,
+        conflicts_with = "compact"
+    )]
+    indent: usize,
+    /// Indent the output with tabs
+    #[arg(long, conflicts_with_all = ["compact", "indent"])]
+    tab: bool,
+    /// Sort the keys of every Object in the output
+    #[arg(long)]
+    sort_keys: bool,
+    /// Escape the characters that are not ASCII in the output
+    #[arg(long)]
+    ascii: bool,
+    /// Print the Strings left in the output without quotes, one per line
+    #[arg(short, long, group = "output_mode")]
+    raw_output: bool,
+    /// Like --raw-output, without a newline between the values
+    #[arg(long, conflicts_with_all = ["preserve_format", "flatten", "emit_patch"])]
+    join: bool,
+    /// Print every value as a `path = value;` line, to grep and diff it
+    #[arg(long, group = "output_mode")]
+    flatten: bool,
+    /// Read the input as `path = value;` lines, as printed by --flatten
+    #[arg(long)]
+    unflatten: bool,
+    /// Print a JSON Patch (RFC 6902) that turns the input into the output instead of the
+    /// document. It is computed by comparing the two documents, so a key renamed with S is a
+    /// remove and an add
+    #[arg(long, group = "output_mode")]
+    emit_patch: bool,
+    /// Apply a JSON Patch (RFC 6902) file to the input before running the script
+    #[arg(long, value_name = "FILE")]
+    apply_patch: Option<String>,
+    /// Apply a JSON Merge Patch (RFC 7396) file to the input before running the script
+    #[arg(long, value_name = "FILE")]
+    merge_patch: Option<String>,
+    /// Write the result back to the input file instead of printing it
+    #[arg(short, long, requires = "input_files")]
+    in_place: bool,
+    /// Print what the script would change instead of the result: a unified diff of the output,
+    /// or the list of the changed paths
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "unified",
+        conflicts_with = "emit_patch"
+    )]
+    diff: Option<DiffFormat>,
+    /// Format of the input, guessed from the extension of the input file by default
+    #[arg(long, value_name = "FORMAT")]
+    from: Option<Format>,
+    /// Format of the output, the format of the input by default
+    #[arg(long, value_name = "FORMAT", conflicts_with_all = ["output_mode", "join"])]
+    to: Option<Format>,
+    /// Accept comments and trailing commas in the input, same as --from jsonc
+    #[arg(long, conflicts_with_all = ["from", "json5"])]
+    jsonc: bool,
+    /// Read the input as JSON5, same as --from json5
+    #[arg(long, conflicts_with = "from")]
+    json5: bool,
+    /// Columns of the CSV or TSV output, in order, e.g. `--columns name,id`
+    #[arg(long, value_delimiter = ',', value_name = "COLUMNS")]
+    columns: Vec<String>,
+    /// Read the CSV or TSV cells that look like Numbers or Booleans as such, and the empty ones
+    /// as null
+    #[arg(long)]
+    infer_types: bool,
+    /// Run a single p, d, s or S command while the input is read, to edit JSON documents larger
+    /// than the memory
+    #[arg(long, conflicts_with_all = [
+        "preserve_format", "sort_keys", "raw_output", "join", "flatten", "unflatten", "emit_patch",
+        "apply_patch", "merge_patch", "in_place", "diff", "from", "to", "jsonc", "json5",
+    ])]
+    stream: bool,
+    /// Read the input as newline delimited JSON: a document per line, printed on a line
+    #[arg(long, conflicts_with_all = [
+        "preserve_format", "unflatten", "from", "to", "jsonc", "json5", "stream",
+    ])]
+    ndjson: bool,
+    /// Run the script on N documents (or files) at a time, 0 for one per CPU. The output keeps
+    /// the order of the input
+    #[arg(
+        short,
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        conflicts_with = "stream"
+    )]
+    jobs: usize,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum DiffFormat {
+    Unified,
+    Paths,
 }
 
-mod grammar;
-use grammar::{parse_grammar, JedCommand};
+use jed::{Program, ProgramOutput, QuitParams};
 
-mod commands;
-use commands::{
-    delete_on_specified_ranges, print_on_specified_ranges, substitute_keys,
-    substitute_keys_on_specified_ranges, substitute_values, substitute_values_on_specified_ranges,
-};
+mod cst;
+mod diff;
+mod flatten;
+mod format;
+use format::Format;
+mod output;
+mod patch;
+mod stream;
+mod table;
+use output::OutputOptions;
 
-fn main() -> Result<()> {
+/// Exit status when no filter matched anything, see `--no-match-ok`.
+const EXIT_NO_MATCH: i32 = 1;
+/// Exit status when jed fails (bad command, unreadable or invalid input...).
+const EXIT_ERROR: i32 = 2;
+
+fn main() {
     // Restore default SIGPIPE handling
     unsafe {
         libc::signal(libc::SIGPIPE, libc::SIG_DFL);
     }
 
     let cli = Cli::parse();
-    let mut file_contents = String::from("");
+    match run(cli) {
+        Ok(exit_code) => std::process::exit(exit_code),
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+}
 
-    match cli.input_file {
-        Some(ref input_file) => {
-            file_contents = std::fs::read_to_string(input_file)
-                .with_context(|| "Could not read file".to_string())?;
-            file_contents = file_contents.parse::<String>()?;
+/// Runs jed and returns its exit status.
+fn run(cli: Cli) -> Result<i32> {
+    if cli.stream {
+        return run_stream(&cli);
+    }
+    let script = Script {
+        program: Program::compile_all(&cli.expression)?,
+        json_patch: match cli.apply_patch {
+            Some(ref file_name) => Some(read_json_file(file_name)?),
+            None => None,
+        },
+        merge_patch: match cli.merge_patch {
+            Some(ref file_name) => Some(read_json_file(file_name)?),
+            None => None,
+        },
+    };
+    let input_files: Vec<Option<&str>> = if cli.input_files.is_empty() {
+        vec![None]
+    } else {
+        cli.input_files.iter().map(|f| Some(f.as_str())).collect()
+    };
+    let jobs = match cli.jobs {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        jobs => jobs,
+    };
+    // `--diff` and `--emit-patch` print what `--in-place` would do
+    let in_place = cli.in_place && cli.diff.is_none() && !cli.emit_patch;
+
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+    let mut matches = 0;
+    let mut quit = None;
+    if let [input_file] = input_files[..] {
+        // A single input is printed as it is run, unless it goes back to its file
+        let mut printed = Vec::new();
+        let out: &mut dyn Write = if in_place { &mut printed } else { &mut stdout };
+        let result = run_input(&cli, &script, input_file, jobs, out)?;
+        (matches, quit) = (result.matches, result.quit);
+        if let Some(input_file) = input_file.filter(|_| in_place) {
+            // Like sed, `Q` leaves the file as it was
+            if !matches!(quit, Some(QuitParams { print: false, .. })) {
+                write_in_place(input_file, &printed)?
+            }
+        }
+    } else {
+        // The files are shared among the threads
+        parallel_for_each(
+            jobs,
+            input_files.into_iter(),
+            |input_file| {
+                let mut printed = Vec::new();
+                let result = run_input(&cli, &script, input_file, 1, &mut printed);
+                (input_file, result, printed)
+            },
+            |(input_file, result, printed)| {
+                let result = result?;
+                matches += result.matches;
+                quit = result.quit;
+                match input_file {
+                    Some(input_file) if in_place => {
+                        if !matches!(quit, Some(QuitParams { print: false, .. })) {
+                            write_in_place(input_file, &printed)?
+                        }
+                    }
+                    _ => stdout.write_all(&printed)?,
+                }
+                // Like sed, `q` and `Q` leave the next files as they are
+                Ok(quit.is_none())
+            },
+        )?;
+    }
+    stdout.flush()?;
+    if let Some(params) = quit {
+        return Ok(params.exit_code.into());
+    }
+    // Only patching the input is a success on its own
+    if matches == 0 && !cli.no_match_ok && !cli.expression.is_empty() {
+        return Ok(EXIT_NO_MATCH);
+    }
+    Ok(0)
+}
+
+/// The program and the patches to apply before it, shared by the threads of `--jobs`.
+struct Script {
+    program: Program,
+    json_patch: Option<Value>,
+    merge_patch: Option<Value>,
+}
+
+impl Script {
+    fn run(&self, mut v: Value) -> Result<ProgramOutput> {
+        if let Some(ref json_patch) = self.json_patch {
+            v = patch::apply_json_patch(v, json_patch)?;
+        }
+        if let Some(ref merge_patch) = self.merge_patch {
+            patch::apply_merge_patch(&mut v, merge_patch);
+        }
+        Ok(self.program.run(v)?)
+    }
+}
+
+/// The result of running the script on an input file, or on stdin.
+struct InputOutput {
+    matches: usize,
+    quit: Option<QuitParams>,
+}
+
+/// A document of the input once the script ran on it.
+struct DocumentOutput {
+    /// The document before the script, kept for `--diff` and `--emit-patch`.
+    input: Option<Value>,
+    /// The document after the script, unless it was already written to `text`.
+    output: ProgramOutput,
+    text: Option<Vec<u8>>,
+}
+
+/// Reads the documents of an input file (stdin if `None`) and runs the script on them, on `jobs`
+/// threads. Each document is written to `out` as soon as the ones before it are.
+fn run_input(
+    cli: &Cli,
+    script: &Script,
+    input_file: Option<&str>,
+    jobs: usize,
+    out: &mut dyn Write,
+) -> Result<InputOutput> {
+    let mut input = Vec::new();
+
+    match input_file {
+        Some(input_file) => {
+            input = std::fs::read(input_file)
+                .with_context(|| format!("Could not read file {input_file}"))?;
         }
         None => {
-            std::io::stdin().read_to_string(&mut file_contents)?;
+            std::io::stdin().read_to_end(&mut input)?;
         }
     }
 
-    let input = &cli.expression;
-    let mut v: Value = serde_json::from_str(&file_contents)
-        .with_context(|| "Could not parse file into JSON".to_string())?;
-    let (stack, command) = parse_grammar(input)?;
-
-    match command {
-        JedCommand::Substitute(params) => {
-            let pattern = params.pattern;
-            let replacement = params.replacement;
-            if !stack.is_empty() {
-                v = substitute_values_on_specified_ranges(v, &stack, &pattern, &replacement);
-            } else {
-                v = substitute_values(v, &pattern, &replacement);
-            }
+    let from = cli
+        .from
+        .or(cli.jsonc.then_some(Format::Jsonc))
+        .or(cli.json5.then_some(Format::Json5))
+        .or_else(|| input_file.and_then(Format::from_extension))
+        .unwrap_or(Format::Json);
+    let to = cli.to.unwrap_or(from);
+    if cli.preserve_format && !(matches!(from, Format::Json | Format::Jsonc) && to.is_json()) {
+        anyhow::bail!("--preserve-format only works on JSON and JSONC");
+    }
+    // The binary formats are decoded from the bytes, the others from the text
+    let file_contents = if from.is_binary() {
+        String::new()
+    } else {
+        String::from_utf8(std::mem::take(&mut input)).context("Could not read file as UTF-8")?
+    };
+    let cst = if cli.preserve_format {
+        let lenient = from == Format::Jsonc;
+        Some(cst::parse(&file_contents, lenient).context("Could not parse file into JSON")?)
+    } else {
+        None
+    };
+    let datetimes = if from == Format::Toml && to == Format::Toml {
+        format::toml_datetimes(&file_contents)?
+    } else {
+        HashSet::new()
+    };
+    let binary = if from.is_binary() && to.is_binary() {
+        format::binary_paths(&input, from)?
+    } else {
+        HashMap::new()
+    };
+    let source = Source {
+        text: &file_contents,
+        cst: cst.as_ref(),
+        datetimes: &datetimes,
+        binary: &binary,
+    };
+
+    let keep_documents = cli.emit_patch || cli.diff.is_some();
+    let run_document = |v: Value| -> Result<DocumentOutput> {
+        let input = keep_documents.then(|| v.clone());
+        let mut output = script.run(v)?;
+        let text = if keep_documents {
+            None
+        } else {
+            let text = format_document(cli, to, &output.v, &source)?;
+            output.v = Value::Null;
+            Some(text)
+        };
+        Ok(DocumentOutput {
+            input,
+            output,
+            text,
+        })
+    };
+
+    let separator = format::document_separator(to);
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+    let mut written = 0;
+    let mut matches = 0;
+    let mut quit = None;
+    let consume = |document: Result<DocumentOutput>| -> Result<bool> {
+        let document = document?;
+        matches += document.output.matches;
+        quit = document.output.quit;
+        // `Q` drops the current document, `q` keeps it, both drop the ones after it
+        if matches!(quit, Some(QuitParams { print: false, .. })) {
+            return Ok(false);
         }
-        JedCommand::SubstituteKeys(params) => {
-            let pattern = params.pattern;
-            let replacement = params.replacement;
-            if !stack.is_empty() {
-                v = substitute_keys_on_specified_ranges(v, &stack, &pattern, &replacement);
-            } else {
-                v = substitute_keys(v, &pattern, &replacement);
+        inputs.extend(document.input);
+        if let Some(text) = document.text {
+            if written > 0 {
+                out.write_all(separator.as_bytes())?;
             }
+            out.write_all(&text)?;
+            written += 1;
+        }
+        if keep_documents {
+            outputs.push(document.output.v);
         }
-        JedCommand::Print => {
-            v = print_on_specified_ranges(v, &stack);
+        Ok(quit.is_none())
+    };
+    if cli.ndjson {
+        let lines = file_contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let run_line = |(i, line): (usize, &str)| {
+            let v = serde_json::from_str(line)
+                .with_context(|| format!("Could not parse line {} into JSON", i + 1))?;
+            run_document(v)
+        };
+        parallel_for_each(jobs, lines, run_line, consume)?;
+    } else {
+        let documents = match cst {
+            Some(ref root) => vec![cst::to_value(&file_contents, root)?],
+            None if cli.unflatten => vec![flatten::unflatten(&file_contents)?],
+            None if from.is_binary() => format::parse_binary_documents(&input, from)?,
+            None => format::parse_documents(&file_contents, from, cli.infer_types)?,
+        };
+        parallel_for_each(jobs, documents.into_iter(), run_document, consume)?;
+    }
+
+    let printed = match cli.diff {
+        Some(DiffFormat::Unified) => {
+            // The binary formats are compared as JSON
+            let shown = if to.is_binary() { Format::Json } else { to };
+            let before = format_documents(cli, shown, &inputs, &source)?;
+            let after = format_documents(cli, shown, &outputs, &source)?;
+            let (before, after) = (String::from_utf8(before)?, String::from_utf8(after)?);
+            let file_name = input_file.unwrap_or("stdin");
+            diff::unified(&before, &after, file_name).into_bytes()
         }
-        JedCommand::Delete => {
-            v = delete_on_specified_ranges(v, &stack);
+        Some(DiffFormat::Paths) => inputs
+            .iter()
+            .zip(&outputs)
+            .map(|(input, v)| diff::paths(input, v))
+            .collect::<String>()
+            .into_bytes(),
+        None if cli.emit_patch => {
+            let mut printed = String::new();
+            for (input, v) in inputs.iter().zip(&outputs) {
+                let patch = diff::to_json_patch(&diff::diff(input, v));
+                printed += &(output::to_text(&patch, &output_options(cli))? + "\n");
+            }
+            printed.into_bytes()
         }
-        JedCommand::Other(_) => {
-            println!("Only substitute command is supported for now");
-            std::process::exit(1);
+        None => Vec::new(),
+    };
+    out.write_all(&printed)?;
+    Ok(InputOutput { matches, quit })
+}
+
+/// Runs the command given with `--stream` on the input, printing the output as it is read.
+fn run_stream(cli: &Cli) -> Result<i32> {
+    let program = Program::compile_all(&cli.expression)?;
+    let mut commands = program.commands();
+    let (Some((stack, command)), None) = (commands.next(), commands.next()) else {
+        anyhow::bail!("--stream runs a single command");
+    };
+    let mut output = std::io::BufWriter::new(std::io::stdout().lock());
+    let options = output_options(cli);
+    let matches = if cli.input_files.is_empty() {
+        stream::run(
+            std::io::stdin().lock(),
+            &mut output,
+            stack,
+            command,
+            &options,
+        )?
+    } else {
+        let mut matches = 0;
+        for input_file in &cli.input_files {
+            let input = std::fs::File::open(input_file)
+                .with_context(|| format!("Could not read file {input_file}"))?;
+            let input = std::io::BufReader::new(input);
+            matches += stream::run(input, &mut output, stack, command, &options)?;
         }
+        matches
+    };
+    if matches == 0 && !cli.no_match_ok {
+        return Ok(EXIT_NO_MATCH);
+    }
+    Ok(0)
+}
+
+/// What the output is written from, besides the document: the input as it was read.
+struct Source<'a> {
+    text: &'a str,
+    /// The syntax tree of the input, with `--preserve-format`.
+    cst: Option<&'a cst::Node>,
+    /// The paths of the dates and times of a TOML input.
+    datetimes: &'a HashSet<String>,
+    /// The paths of the binary data of a MessagePack or CBOR input.
+    binary: &'a HashMap<String, format::Binary>,
+}
+
+/// Writes the documents the way they are printed, following the output options.
+fn format_documents(
+    cli: &Cli,
+    to: Format,
+    documents: &[Value],
+    source: &Source,
+) -> Result<Vec<u8>> {
+    let mut texts = Vec::new();
+    for v in documents {
+        texts.push(format_document(cli, to, v, source)?);
     }
+    Ok(texts.join(format::document_separator(to).as_bytes()))
+}
 
-    let output = if cli.no_color {
-        serde_json::to_string_pretty(&v).context("Failed making JSON pretty")?
+fn format_document(cli: &Cli, to: Format, v: &Value, source: &Source) -> Result<Vec<u8>> {
+    let text = if let Some(root) = source.cst {
+        cst::render(source.text, root, v)?
+    } else if cli.flatten {
+        flatten::flatten(v)?
+    } else if cli.raw_output || cli.join {
+        output::to_raw_text(v, cli.join)?
+    } else if to.is_json() {
+        output::to_text(v, &output_options(cli))? + "\n"
+    } else if to.is_binary() {
+        return format::to_bytes(v, to, source.binary);
     } else {
-        to_colored_json_auto(&v).context("Failed to colorize JSON output")?
+        format::to_text(v, to, &cli.columns, source.datetimes)?
     };
-    println!("{}", output);
-    Ok(())
+    Ok(text.into_bytes())
+}
+
+/// Replaces the contents of the file, through a temporary file so that it is never left half
+/// written.
+fn write_in_place(file_name: &str, contents: &[u8]) -> Result<()> {
+    let path = std::path::Path::new(file_name);
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(path.file_name().context("Invalid input file name")?);
+    temp_name.push(".jed-tmp");
+    let temp_path = path.with_file_name(temp_name);
+    std::fs::write(&temp_path, contents)
+        .with_context(|| format!("Could not write {}", temp_path.display()))?;
+    let permissions = std::fs::metadata(path)
+        .with_context(|| format!("Could not read file {file_name}"))?
+        .permissions();
+    std::fs::set_permissions(&temp_path, permissions)?;
+    std::fs::rename(&temp_path, path).with_context(|| format!("Could not write {file_name}"))
+}
+
+fn output_options(cli: &Cli) -> OutputOptions {
+    OutputOptions {
+        color: !cli.no_color && !cli.in_place && cli.diff.is_none(),
+        compact: cli.compact || cli.ndjson,
+        indent: if cli.tab {
+            String::from("\t")
+        } else {
+            " ".repeat(cli.indent)
+        },
+        sort_keys: cli.sort_keys,
+        ascii: cli.ascii,
+    }
+}
+
+/// Reads a JSON file, for the patches of `--apply-patch` and `--merge-patch`.
+fn read_json_file(file_name: &str) -> Result<Value> {
+    let contents = std::fs::read_to_string(file_name)
+        .with_context(|| format!("Could not read file {file_name}"))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Could not parse {file_name} into JSON"))
+}
+
+/// Runs `f` on the items on `jobs` threads, and hands the results to `consume` in the order of
+/// the items. Stops taking items once `consume` returns `false` (after a `q`).
+fn parallel_for_each<T: Send, U: Send>(
+    jobs: usize,
+    items: impl Iterator<Item = T> + Send,
+    f: impl Fn(T) -> U + Sync,
+    mut consume: impl FnMut(U) -> Result<bool>,
+) -> Result<()> {
+    if jobs <= 1 {
+        for item in items {
+            if !consume(f(item))? {
+                break;
+            }
+        }
+        return Ok(());
+    }
+    // The items taken but not consumed yet, so a slow item doesn't leave the ones after it piling
+    // up in memory
+    let window = jobs * 4;
+    let queue = Mutex::new(Queue {
+        items,
+        taken: 0,
+        consumed: 0,
+        stop: false,
+    });
+    let ready = Condvar::new();
+    let (sender, receiver) = mpsc::sync_channel(window);
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (queue, ready, f) = (&queue, &ready, &f);
+            scope.spawn(move || loop {
+                let queue = queue.lock().unwrap();
+                let mut queue = ready
+                    .wait_while(queue, |q| !q.stop && q.taken >= q.consumed + window)
+                    .unwrap();
+                if queue.stop {
+                    break;
+                }
+                let Some(item) = queue.items.next() else {
+                    break;
+                };
+                let i = queue.taken;
+                queue.taken += 1;
+                drop(queue);
+                if sender.send((i, f(item))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        // The results come in any order, they wait here for the ones before them
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        let mut consume_in_order = || -> Result<()> {
+            for (i, result) in receiver.iter() {
+                pending.insert(i, result);
+                while let Some(result) = pending.remove(&next) {
+                    next += 1;
+                    queue.lock().unwrap().consumed = next;
+                    ready.notify_all();
+                    if !consume(result)? {
+                        return Ok(());
+                    }
+                }
+            }
+            Ok(())
+        };
+        let result = consume_in_order();
+        queue.lock().unwrap().stop = true;
+        ready.notify_all();
+        // Unblocks the threads waiting to send their result
+        drop(receiver);
+        result
+    })
+}
+
+/// The items of [`parallel_for_each`], and how far the threads and `consume` got in them.
+struct Queue<I> {
+    items: I,
+    taken: usize,
+    consumed: usize,
+    stop: bool,
 }
 
 #[cfg(test)]
+#[path = "cli_tests.rs"]
 mod tests;
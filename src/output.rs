@@ -0,0 +1,116 @@
+use anyhow::{Context, Result};
+use colored_json::{ColorMode, ColoredFormatter, Output};
+use serde_json::ser::{CompactFormatter, PrettyFormatter};
+use serde_json::Value;
+use std::fmt::Write;
+
+/// How the resulting document is printed.
+pub struct OutputOptions {
+    /// Color the output, if it goes to a terminal.
+    pub color: bool,
+    /// Print everything on a single line, without spaces.
+    pub compact: bool,
+    /// What each level is indented with, unless `compact`.
+    pub indent: String,
+    pub sort_keys: bool,
+    /// Escape the characters that are not ASCII as `\uXXXX`.
+    pub ascii: bool,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            color: false,
+            compact: false,
+            indent: String::from("  "),
+            sort_keys: false,
+            ascii: false,
+        }
+    }
+}
+
+/// Writes the document as JSON text, following the options.
+pub fn to_text(v: &Value, options: &OutputOptions) -> Result<String> {
+    let sorted;
+    let v = if options.sort_keys {
+        let mut copy = v.clone();
+        sort_keys(&mut copy);
+        sorted = copy;
+        &sorted
+    } else {
+        v
+    };
+    let mode = if options.color {
+        ColorMode::Auto(Output::StdOut)
+    } else {
+        ColorMode::Off
+    };
+    let text = if options.compact {
+        ColoredFormatter::new(CompactFormatter).to_colored_json(v, mode)
+    } else {
+        ColoredFormatter::new(PrettyFormatter::with_indent(options.indent.as_bytes()))
+            .to_colored_json(v, mode)
+    }
+    .context("Failed to format JSON output")?;
+    if options.ascii {
+        return Ok(escape_non_ascii(&text));
+    }
+    Ok(text)
+}
+
+/// Sorts the keys of every Object in the document.
+fn sort_keys(v: &mut Value) {
+    match v {
+        Value::Object(map) => {
+            map.sort_keys();
+            map.values_mut().for_each(sort_keys);
+        }
+        Value::Array(vec) => vec.iter_mut().for_each(sort_keys),
+        _ => (),
+    }
+}
+
+/// Escapes the characters that are not ASCII, which in JSON text can only be inside strings.
+pub fn escape_non_ascii(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii() {
+            out.push(c);
+        } else {
+            let mut buf = [0u16; 2];
+            for unit in c.encode_utf16(&mut buf) {
+                let _ = write!(out, "\\u{unit:04x}");
+            }
+        }
+    }
+    out
+}
+
+/// Writes the leaves of the document (what `p` kept), one per line and Strings without quotes,
+/// for `-r`. With `join`, the leaves are written one after the other instead.
+pub fn to_raw_text(v: &Value, join: bool) -> Result<String> {
+    let mut leaves = Vec::new();
+    collect_raw_leaves(v, &mut leaves)?;
+    if join {
+        return Ok(leaves.concat());
+    }
+    Ok(leaves.into_iter().map(|leaf| leaf + "\n").collect())
+}
+
+fn collect_raw_leaves(v: &Value, leaves: &mut Vec<String>) -> Result<()> {
+    match v {
+        Value::Object(map) if !map.is_empty() => {
+            for value in map.values() {
+                collect_raw_leaves(value, leaves)?;
+            }
+        }
+        Value::Array(vec) if !vec.is_empty() => {
+            for value in vec {
+                collect_raw_leaves(value, leaves)?;
+            }
+        }
+        Value::String(s) => leaves.push(s.clone()),
+        _ => leaves.push(serde_json::to_string(v).context("Failed to format JSON output")?),
+    }
+    Ok(())
+}
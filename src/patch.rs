@@ -0,0 +1,189 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+
+/// Applies a JSON Patch (RFC 6902): an Array of `add`, `remove`, `replace`, `move`, `copy` and
+/// `test` operations. Fails, without applying anything, if an operation can't be applied.
+pub fn apply_json_patch(mut v: Value, patch: &Value) -> Result<Value> {
+    let operations = patch
+        .as_array()
+        .context("A JSON Patch must be an Array of operations")?;
+    for (i, operation) in operations.iter().enumerate() {
+        apply_operation(&mut v, operation)
+            .with_context(|| format!("Could not apply operation {i} of the JSON Patch"))?;
+    }
+    Ok(v)
+}
+
+fn apply_operation(v: &mut Value, operation: &Value) -> Result<()> {
+    let field = |name: &str| {
+        operation
+            .get(name)
+            .with_context(|| format!("Missing \"{name}\" in {operation}"))
+    };
+    let pointer = |name: &str| -> Result<Vec<String>> {
+        parse_pointer(field(name)?.as_str().context("A path must be a String")?)
+    };
+    let op = field("op")?.as_str().context("\"op\" must be a String")?;
+    match op {
+        "add" => add(v, &pointer("path")?, field("value")?.clone()),
+        "remove" => remove(v, &pointer("path")?).map(|_| ()),
+        "replace" => {
+            let path = pointer("path")?;
+            *get_mut(v, &path)? = field("value")?.clone();
+            Ok(())
+        }
+        "move" => {
+            let from = pointer("from")?;
+            let path = pointer("path")?;
+            if path.len() > from.len() && path.starts_with(&from) {
+                bail!("Can't move a value into one of its children");
+            }
+            let value = remove(v, &from)?;
+            add(v, &path, value)
+        }
+        "copy" => {
+            let value = get_mut(v, &pointer("from")?)?.clone();
+            add(v, &pointer("path")?, value)
+        }
+        "test" => {
+            let path = pointer("path")?;
+            if !equal(get_mut(v, &path)?, field("value")?) {
+                bail!("Test failed, {} is not {}", field("path")?, field("value")?);
+            }
+            Ok(())
+        }
+        _ => bail!("Unknown operation {op}"),
+    }
+}
+
+/// Compares two values the way the `test` operation does: Numbers by their value (`1.0` is
+/// `1`), which `==` doesn't do on the text kept by `arbitrary_precision`.
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => normalize(a) == normalize(b),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equal(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len() && a.iter().all(|(k, a)| b.get(k).is_some_and(|b| equal(a, b)))
+        }
+        _ => a == b,
+    }
+}
+
+/// A Number as its sign, its significant digits and the exponent they go with, e.g. `-1.50e2`
+/// as `(true, "15", 1)`. Equal Numbers give the same triple, whatever their text.
+fn normalize(n: &serde_json::Number) -> (bool, String, i64) {
+    let text = n.to_string();
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text.to_string()),
+        None => (false, text),
+    };
+    let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse().unwrap_or(0)),
+        None => (text.as_str(), 0i64),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{integer}{fraction}");
+    let digits = digits.trim_start_matches('0');
+    let significant = digits.trim_end_matches('0');
+    if significant.is_empty() {
+        return (false, String::new(), 0);
+    }
+    let exponent = exponent - fraction.len() as i64 + (digits.len() - significant.len()) as i64;
+    (negative, significant.to_string(), exponent)
+}
+
+/// Splits a JSON Pointer (RFC 6901), e.g. `/a~1b/0` into `["a/b", "0"]`.
+fn parse_pointer(pointer: &str) -> Result<Vec<String>> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(tokens) = pointer.strip_prefix('/') else {
+        bail!("Invalid JSON Pointer {pointer}");
+    };
+    Ok(tokens
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+fn array_index(token: &str, len: usize) -> Result<usize> {
+    match token.parse::<usize>() {
+        Ok(i) if i < len && (token == "0" || !token.starts_with('0')) => Ok(i),
+        _ => bail!("Invalid Array index {token}"),
+    }
+}
+
+fn get_mut<'a>(v: &'a mut Value, path: &[String]) -> Result<&'a mut Value> {
+    let mut node = v;
+    for token in path {
+        node = match node {
+            Value::Object(map) => map
+                .get_mut(token)
+                .with_context(|| format!("Key {token} not found"))?,
+            Value::Array(vec) => {
+                let i = array_index(token, vec.len())?;
+                &mut vec[i]
+            }
+            _ => bail!("Can't find {token} in {node}"),
+        };
+    }
+    Ok(node)
+}
+
+fn add(v: &mut Value, path: &[String], value: Value) -> Result<()> {
+    let Some((last, parent)) = path.split_last() else {
+        *v = value;
+        return Ok(());
+    };
+    match get_mut(v, parent)? {
+        Value::Object(map) => {
+            map.insert(last.clone(), value);
+        }
+        Value::Array(vec) if last == "-" => vec.push(value),
+        Value::Array(vec) => {
+            let i = array_index(last, vec.len() + 1)?;
+            vec.insert(i, value);
+        }
+        node => bail!("Can't add {last} to {node}"),
+    }
+    Ok(())
+}
+
+fn remove(v: &mut Value, path: &[String]) -> Result<Value> {
+    let Some((last, parent)) = path.split_last() else {
+        return Ok(std::mem::take(v));
+    };
+    match get_mut(v, parent)? {
+        Value::Object(map) => map
+            .shift_remove(last)
+            .with_context(|| format!("Key {last} not found")),
+        Value::Array(vec) => {
+            let i = array_index(last, vec.len())?;
+            Ok(vec.remove(i))
+        }
+        node => bail!("Can't remove {last} from {node}"),
+    }
+}
+
+/// Applies a JSON Merge Patch (RFC 7396): the keys of the patch are merged recursively into the
+/// document, and the keys set to `null` are removed.
+pub fn apply_merge_patch(v: &mut Value, patch: &Value) {
+    let Value::Object(patch_map) = patch else {
+        *v = patch.clone();
+        return;
+    };
+    if !v.is_object() {
+        *v = Value::Object(Map::new());
+    }
+    if let Value::Object(map) = v {
+        for (k, patch_value) in patch_map {
+            if patch_value.is_null() {
+                map.shift_remove(k);
+            } else {
+                apply_merge_patch(map.entry(k.clone()).or_insert(Value::Null), patch_value);
+            }
+        }
+    }
+}
@@ -0,0 +1,715 @@
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::io::{BufRead, Write};
+
+use crate::output::{escape_non_ascii, OutputOptions};
+use jed::{JedCommand, RangeType, SubstituteParams};
+
+/// What the JSON reader finds, in document order.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    StartObject,
+    EndObject,
+    StartArray,
+    EndArray,
+    Key(String),
+    /// A String, Number, Bool or Null.
+    Scalar(Value),
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    /// A value is expected: the root, an element or the value of a member.
+    Value,
+    /// Just after `{`: a key or `}`.
+    ObjectStart,
+    /// Just after `[`: a value or `]`.
+    ArrayStart,
+    /// After a value: `,` or the end of the container, or the end of the input at the root.
+    AfterValue,
+    Done,
+}
+
+/// Reads a JSON document one token at a time, without building it in memory.
+pub struct Reader<R> {
+    input: std::io::Bytes<R>,
+    peeked: Option<u8>,
+    position: usize,
+    /// Whether each open container is an Object.
+    containers: Vec<bool>,
+    state: State,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(input: R) -> Self {
+        Reader {
+            input: input.bytes(),
+            peeked: None,
+            position: 0,
+            containers: Vec::new(),
+            state: State::Value,
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>> {
+        if self.peeked.is_none() {
+            self.peeked = self.input.next().transpose()?;
+        }
+        Ok(self.peeked)
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        let byte = self.peek()?;
+        self.peeked = None;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn skip_whitespace(&mut self) -> Result<Option<u8>> {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek()? {
+            self.next_byte()?;
+        }
+        self.peek()
+    }
+
+    fn error(&self, expected: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "Could not parse file into JSON: expected {expected} at byte {}",
+            self.position
+        )
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if self.skip_whitespace()? != Some(byte) {
+            return Err(self.error(&format!("`{}`", byte as char)));
+        }
+        self.next_byte()?;
+        Ok(())
+    }
+
+    /// The next token of the document, `None` at its end.
+    pub fn next_event(&mut self) -> Result<Option<Event>> {
+        let byte = self.skip_whitespace()?;
+        match self.state {
+            State::Done => match byte {
+                None => Ok(None),
+                Some(_) => Err(self.error("the end of the input")),
+            },
+            State::Value => self.value(),
+            State::ObjectStart if byte == Some(b'}') => self.close(),
+            State::ObjectStart => self.key(),
+            State::ArrayStart if byte == Some(b']') => self.close(),
+            State::ArrayStart => self.value(),
+            State::AfterValue => match (self.containers.last(), byte) {
+                (None, _) => {
+                    self.state = State::Done;
+                    self.next_event()
+                }
+                (Some(_), Some(b',')) => {
+                    self.next_byte()?;
+                    if self.containers.last() == Some(&true) {
+                        self.key()
+                    } else {
+                        self.value()
+                    }
+                }
+                (Some(true), Some(b'}')) | (Some(false), Some(b']')) => self.close(),
+                (Some(true), _) => Err(self.error("`,` or `}`")),
+                (Some(false), _) => Err(self.error("`,` or `]`")),
+            },
+        }
+    }
+
+    fn close(&mut self) -> Result<Option<Event>> {
+        self.next_byte()?;
+        self.state = State::AfterValue;
+        Ok(Some(match self.containers.pop() {
+            Some(true) => Event::EndObject,
+            _ => Event::EndArray,
+        }))
+    }
+
+    fn key(&mut self) -> Result<Option<Event>> {
+        if self.skip_whitespace()? != Some(b'"') {
+            return Err(self.error("a key"));
+        }
+        let key = self.string()?;
+        self.expect(b':')?;
+        self.state = State::Value;
+        Ok(Some(Event::Key(key)))
+    }
+
+    fn value(&mut self) -> Result<Option<Event>> {
+        let event = match self.skip_whitespace()? {
+            Some(b'{') => {
+                self.next_byte()?;
+                self.containers.push(true);
+                self.state = State::ObjectStart;
+                return Ok(Some(Event::StartObject));
+            }
+            Some(b'[') => {
+                self.next_byte()?;
+                self.containers.push(false);
+                self.state = State::ArrayStart;
+                return Ok(Some(Event::StartArray));
+            }
+            Some(b'"') => Event::Scalar(Value::String(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => {
+                let text =
+                    self.token(|b| matches!(b, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))?;
+                match serde_json::from_str(&text) {
+                    Ok(number) => Event::Scalar(Value::Number(number)),
+                    Err(_) => return Err(self.error("a number")),
+                }
+            }
+            Some(b'a'..=b'z') => match self.token(|b| b.is_ascii_lowercase())?.as_str() {
+                "true" => Event::Scalar(Value::Bool(true)),
+                "false" => Event::Scalar(Value::Bool(false)),
+                "null" => Event::Scalar(Value::Null),
+                _ => return Err(self.error("a value")),
+            },
+            _ => return Err(self.error("a value")),
+        };
+        self.state = State::AfterValue;
+        Ok(Some(event))
+    }
+
+    fn token(&mut self, accept: impl Fn(u8) -> bool) -> Result<String> {
+        let mut text = String::new();
+        while let Some(byte) = self.peek()? {
+            if !accept(byte) {
+                break;
+            }
+            text.push(byte as char);
+            self.next_byte()?;
+        }
+        Ok(text)
+    }
+
+    /// Reads a String, quotes included, and unescapes it.
+    fn string(&mut self) -> Result<String> {
+        let mut raw = vec![b'"'];
+        self.next_byte()?;
+        let mut escaped = false;
+        loop {
+            let Some(byte) = self.next_byte()? else {
+                return Err(self.error("the end of the String"));
+            };
+            raw.push(byte);
+            match byte {
+                b'"' if !escaped => break,
+                b'\\' => escaped = !escaped,
+                _ => escaped = false,
+            }
+        }
+        serde_json::from_slice(&raw).map_err(|_| self.error("a valid String"))
+    }
+}
+
+/// A step of the path to a node: its key in an Object, or its index in an Array.
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// How a node is handled, from what its parent found. This follows `apply_on_range` of the
+/// commands step by step, so that a command gives the same result with and without `--stream`.
+#[derive(Clone, Copy)]
+enum Mode {
+    /// The address is looked for from its step `pos`. `anchored` once the first step matched
+    /// above: the next steps must then match right below, the search doesn't start again.
+    Range { pos: usize, anchored: bool },
+    /// Written as it is.
+    Keep,
+    /// Every value below is substituted (`s`).
+    Substitute,
+    /// Every key below is substituted (`S`).
+    SubstituteKeys,
+    /// Becomes `null`, which the parent may leave out.
+    Null,
+    /// Left out.
+    Drop,
+}
+
+/// Which results of its children a container keeps.
+#[derive(Clone, Copy)]
+enum Filter {
+    All,
+    NotNull,
+    NotEmptyOrNull,
+    /// What `d` keeps of an Object it searched: anything but `null` and an empty Object.
+    NotEmptyObjectOrNull,
+}
+
+/// How a container hands its children their mode.
+#[derive(Clone, Copy)]
+enum Children {
+    /// Every child gets the same mode and filter.
+    All(Mode, Filter),
+    /// The step `pos` of the address is a key or index followed by more steps: the children it
+    /// matches go on to the next step.
+    Step { pos: usize, anchored: bool },
+    /// The step `pos` is the last key or index of the address: the command runs on the children
+    /// it matches.
+    Last { pos: usize, anchored: bool },
+}
+
+/// An open container of the document.
+struct Frame {
+    object: bool,
+    /// What is written before the opening bracket: the key of the container in its parent.
+    key: Option<String>,
+    /// Whether the opening bracket was written. A container is written with its first child,
+    /// as some are left out once they end up empty.
+    open: bool,
+    /// Number of members or elements written.
+    written: usize,
+    children: Children,
+    /// Whether the container becomes `null` when it ends up empty.
+    or_null: bool,
+    /// What the parent keeps of the container.
+    filter: Filter,
+    next_key: Option<String>,
+    next_index: usize,
+}
+
+/// The commands that can run on a stream: what happens to a match.
+enum Operation<'a> {
+    Print,
+    Delete,
+    Substitute(&'a SubstituteParams),
+    SubstituteKeys(&'a regex::Regex, &'a String),
+}
+
+/// Runs a single `p`, `d`, `s` or `S` command on the JSON document read from `input`, writing
+/// the result to `output` as it goes. Only the path to the current token is kept in memory, so
+/// the document can be larger than the memory. Returns the number of matches.
+pub fn run(
+    input: impl BufRead,
+    output: impl Write,
+    stack: &[RangeType],
+    command: &JedCommand,
+    options: &OutputOptions,
+) -> Result<usize> {
+    let operation = match command {
+        JedCommand::Print => Operation::Print,
+        JedCommand::Delete => Operation::Delete,
+        JedCommand::Substitute(params) => Operation::Substitute(params),
+        JedCommand::SubstituteKeys(params) => {
+            Operation::SubstituteKeys(&params.pattern, &params.replacement)
+        }
+        _ => bail!("Only the p, d, s and S commands can run on a stream"),
+    };
+    // Without an address, `p` keeps the whole document and `d` drops it, each a single match
+    let (root, matches) = match (&operation, stack.is_empty()) {
+        (_, false) => (
+            Mode::Range {
+                pos: 0,
+                anchored: false,
+            },
+            0,
+        ),
+        (Operation::Print, true) => (Mode::Keep, 1),
+        (Operation::Delete, true) => (Mode::Drop, 1),
+        (Operation::Substitute(_), true) => (Mode::Substitute, 0),
+        (Operation::SubstituteKeys(..), true) => (Mode::SubstituteKeys, 0),
+    };
+    let mut stream = Stream {
+        output,
+        stack,
+        operation,
+        options,
+        root,
+        frames: Vec::new(),
+        skipped: 0,
+        matches,
+        written: false,
+    };
+    let mut reader = Reader::new(input);
+    while let Some(event) = reader.next_event()? {
+        stream.event(event)?;
+    }
+    if !stream.written {
+        stream.output.write_all(b"null")?;
+    }
+    stream.output.write_all(b"\n")?;
+    stream.output.flush()?;
+    Ok(stream.matches)
+}
+
+struct Stream<'a, W> {
+    output: W,
+    stack: &'a [RangeType],
+    operation: Operation<'a>,
+    options: &'a OutputOptions,
+    /// The mode of the document itself.
+    root: Mode,
+    frames: Vec<Frame>,
+    /// Depth inside a container left out or turned to `null`, whose tokens are dropped.
+    skipped: usize,
+    matches: usize,
+    /// Whether anything was written.
+    written: bool,
+}
+
+impl<W: Write> Stream<'_, W> {
+    fn event(&mut self, event: Event) -> Result<()> {
+        if self.skipped > 0 {
+            match event {
+                Event::StartObject | Event::StartArray => self.skipped += 1,
+                Event::EndObject | Event::EndArray => self.skipped -= 1,
+                _ => (),
+            }
+            return Ok(());
+        }
+        match event {
+            Event::Key(k) => {
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.next_key = Some(k);
+                }
+                Ok(())
+            }
+            Event::EndObject | Event::EndArray => self.close(),
+            Event::StartObject => self.node(None, true),
+            Event::StartArray => self.node(None, false),
+            Event::Scalar(v) => self.node(Some(v), false),
+        }
+    }
+
+    /// Handles a scalar (`v`), or the start of a container.
+    fn node(&mut self, v: Option<Value>, object: bool) -> Result<()> {
+        let (mode, filter, key) = match self.frames.last_mut() {
+            Some(frame) => {
+                let segment = if frame.object {
+                    Segment::Key(frame.next_key.take().unwrap_or_default())
+                } else {
+                    frame.next_index += 1;
+                    Segment::Index(frame.next_index - 1)
+                };
+                let children = frame.children;
+                self.child(children, segment, v.as_ref().is_some_and(Value::is_null))
+            }
+            None => (self.root, Filter::All, None),
+        };
+        let (mode, children, or_null) = match (&v, mode) {
+            (None, Mode::Range { pos, anchored }) => match self.container(pos, anchored, object) {
+                Ok(mode) => (mode, Children::All(mode, Filter::All), false),
+                // A container whose children are searched
+                Err((children, or_null)) => (mode, children, or_null),
+            },
+            _ => (mode, Children::All(mode, Filter::All), false),
+        };
+        if v.is_none() && !matches!(mode, Mode::Null | Mode::Drop) {
+            self.frames.push(Frame {
+                object,
+                key,
+                open: false,
+                written: 0,
+                children,
+                or_null,
+                filter,
+                next_key: None,
+                next_index: 0,
+            });
+            return Ok(());
+        }
+        if v.is_none() && matches!(mode, Mode::Null | Mode::Drop) {
+            self.skipped = 1;
+        }
+        let v = match (mode, v) {
+            (Mode::Drop, _) => return Ok(()),
+            (Mode::Null, _) | (_, None) => Value::Null,
+            (mode, Some(v)) => self.scalar(mode, v),
+        };
+        if !keeps(filter, &v) {
+            return Ok(());
+        }
+        self.write_value(key.as_deref(), &v)
+    }
+
+    /// The mode, filter and key (substituted by `S`) of a child of a container.
+    fn child(
+        &mut self,
+        children: Children,
+        segment: Segment,
+        is_null: bool,
+    ) -> (Mode, Filter, Option<String>) {
+        let keep = !matches!(self.operation, Operation::Print);
+        let kept = if keep { Mode::Keep } else { Mode::Drop };
+        let (pos, anchored, last) = match children {
+            Children::All(Mode::SubstituteKeys, filter) => {
+                let key = match segment {
+                    Segment::Key(k) => Some(self.substitute_key(k)),
+                    Segment::Index(_) => None,
+                };
+                return (Mode::SubstituteKeys, filter, key);
+            }
+            Children::All(mode, filter) => {
+                let key = match segment {
+                    Segment::Key(k) => Some(k),
+                    Segment::Index(_) => None,
+                };
+                return (mode, filter, key);
+            }
+            Children::Step { pos, anchored } => (pos, anchored, false),
+            Children::Last { pos, anchored } => (pos, anchored, true),
+        };
+        let matched = match (&self.stack[pos], &segment) {
+            (RangeType::Key(re), Segment::Key(k)) => re.is_match(k),
+            (RangeType::Array(range), Segment::Index(i)) => range.begin <= *i && *i <= range.end,
+            _ => false,
+        };
+        let search = Mode::Range {
+            pos,
+            anchored: false,
+        };
+        let (mode, filter) = match (&segment, last) {
+            (Segment::Key(_), false) if matched => (
+                Mode::Range {
+                    pos: pos + 1,
+                    anchored: true,
+                },
+                Filter::NotNull,
+            ),
+            (Segment::Key(_), false) if anchored => (kept, Filter::All),
+            (Segment::Key(_), false) => (search, Filter::NotNull),
+            (Segment::Index(_), false) if matched => (
+                Mode::Range {
+                    pos: pos + 1,
+                    anchored: true,
+                },
+                Filter::All,
+            ),
+            (Segment::Index(_), false) => (kept, Filter::All),
+            (_, true) => {
+                if matched && matches!(self.operation, Operation::Print | Operation::Delete) {
+                    self.matches += 1;
+                }
+                let mode = match (&self.operation, &segment) {
+                    (Operation::Print, _) if matched => (Mode::Keep, Filter::All),
+                    (Operation::Print, Segment::Key(_)) if !anchored => {
+                        (search, Filter::NotEmptyOrNull)
+                    }
+                    (Operation::Print, _) => (Mode::Drop, Filter::All),
+                    (Operation::Delete, _) if matched => (Mode::Drop, Filter::All),
+                    (Operation::Delete, Segment::Key(_)) if !anchored => {
+                        (search, Filter::NotEmptyObjectOrNull)
+                    }
+                    (Operation::Substitute(_), _) if matched => (Mode::Substitute, Filter::All),
+                    // Searching below a key that doesn't match substitutes all of it, but
+                    // drops `null`
+                    (Operation::Substitute(_), Segment::Key(_)) if !anchored && is_null => {
+                        (Mode::Drop, Filter::All)
+                    }
+                    (Operation::Substitute(_), Segment::Key(_)) if !anchored => {
+                        (Mode::Substitute, Filter::All)
+                    }
+                    (Operation::SubstituteKeys(..), _) if matched => {
+                        (Mode::SubstituteKeys, Filter::All)
+                    }
+                    _ => (Mode::Keep, Filter::All),
+                };
+                let key = match segment {
+                    Segment::Key(k) if matched && matches!(mode.0, Mode::SubstituteKeys) => {
+                        Some(self.substitute_key(k))
+                    }
+                    Segment::Key(k) => Some(k),
+                    Segment::Index(_) => None,
+                };
+                return (mode.0, mode.1, key);
+            }
+        };
+        let key = match segment {
+            Segment::Key(k) => Some(k),
+            Segment::Index(_) => None,
+        };
+        (mode, filter, key)
+    }
+
+    /// How a container looked for from the step `pos` of the address is handled: it becomes
+    /// `Keep` or `Null` as a whole, or its children are searched.
+    fn container(
+        &self,
+        pos: usize,
+        anchored: bool,
+        object: bool,
+    ) -> std::result::Result<Mode, (Children, bool)> {
+        let keep_or_null = if matches!(self.operation, Operation::Print) {
+            Mode::Null
+        } else {
+            Mode::Keep
+        };
+        let last = pos + 1 == self.stack.len();
+        let search = |filter| {
+            Children::All(
+                Mode::Range {
+                    pos,
+                    anchored: false,
+                },
+                filter,
+            )
+        };
+        let substitute = matches!(
+            self.operation,
+            Operation::Substitute(_) | Operation::SubstituteKeys(..)
+        );
+        match (object, &self.stack[pos], last) {
+            (true, RangeType::Key(_), true) => Err((Children::Last { pos, anchored }, !substitute)),
+            (true, RangeType::Key(_), false) => Err((Children::Step { pos, anchored }, true)),
+            (true, RangeType::Value(_), false) => Ok(keep_or_null),
+            (true, _, _) if anchored => Ok(keep_or_null),
+            (true, _, _) => Err((search(Filter::NotNull), true)),
+            (false, RangeType::Array(_), true) => Err((Children::Last { pos, anchored }, false)),
+            (false, RangeType::Array(_), false) => Err((Children::Step { pos, anchored }, false)),
+            (false, RangeType::Value(_), false) => Ok(keep_or_null),
+            (false, _, _) if anchored => Ok(keep_or_null),
+            (false, RangeType::Key(_), true) => Err((search(Filter::NotEmptyOrNull), false)),
+            (false, RangeType::Value(_), true) => Err((search(Filter::NotEmptyOrNull), true)),
+            (false, RangeType::Key(_), false) => Err((search(Filter::NotNull), true)),
+        }
+    }
+
+    /// What a scalar becomes.
+    fn scalar(&mut self, mode: Mode, v: Value) -> Value {
+        match mode {
+            Mode::Substitute => self.substitute(v),
+            Mode::Range { pos, .. } => {
+                let keep = !matches!(self.operation, Operation::Print);
+                let last = pos + 1 == self.stack.len();
+                match (&self.stack[pos], v) {
+                    (_, Value::Null) => Value::Null,
+                    (RangeType::Array(_), Value::String(_)) if last => Value::Null,
+                    (RangeType::Value(re), Value::String(s)) if last => {
+                        let matched = re.is_match(&s);
+                        match self.operation {
+                            Operation::Print | Operation::Delete if matched => {
+                                self.matches += 1;
+                                match self.operation {
+                                    Operation::Print => Value::String(s),
+                                    _ => Value::Null,
+                                }
+                            }
+                            Operation::Print => Value::Null,
+                            Operation::Substitute(_) if matched => self.substitute(Value::String(s)),
+                            _ => Value::String(s),
+                        }
+                    }
+                    (_, v) if keep => v,
+                    _ => Value::Null,
+                }
+            }
+            _ => v,
+        }
+    }
+
+    fn substitute(&mut self, v: Value) -> Value {
+        let Operation::Substitute(params) = self.operation else {
+            return v;
+        };
+        let (new_v, replaced) = params.replace_values(v);
+        self.matches += replaced;
+        new_v
+    }
+
+    fn substitute_key(&mut self, k: String) -> String {
+        let Operation::SubstituteKeys(re, with) = self.operation else {
+            return k;
+        };
+        if re.is_match(&k) {
+            self.matches += 1;
+        }
+        re.replace_all(&k, with.as_str()).into_owned()
+    }
+
+    /// Writes a scalar, or an empty container, after the brackets of the containers above it.
+    fn write_value(&mut self, key: Option<&str>, v: &Value) -> Result<()> {
+        self.open_frames()?;
+        self.write_prefix(self.frames.len(), key)?;
+        let mut text = serde_json::to_string(v)?;
+        if self.options.ascii {
+            text = escape_non_ascii(&text);
+        }
+        self.output.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    /// Writes the opening brackets of the containers that aren't written yet.
+    fn open_frames(&mut self) -> Result<()> {
+        let Some(first) = self.frames.iter().position(|frame| !frame.open) else {
+            return Ok(());
+        };
+        for i in first..self.frames.len() {
+            let key = self.frames[i].key.take();
+            self.write_prefix(i, key.as_deref())?;
+            let frame = &mut self.frames[i];
+            frame.open = true;
+            let bracket = if frame.object { b"{" } else { b"[" };
+            self.output.write_all(bracket)?;
+        }
+        Ok(())
+    }
+
+    /// Writes what comes before a value at `depth`: the comma, the indentation and the key.
+    fn write_prefix(&mut self, depth: usize, key: Option<&str>) -> Result<()> {
+        self.written = true;
+        let Some(parent) = depth.checked_sub(1).map(|i| &mut self.frames[i]) else {
+            return Ok(());
+        };
+        if parent.written > 0 {
+            self.output.write_all(b",")?;
+        }
+        parent.written += 1;
+        if !self.options.compact {
+            self.output.write_all(b"\n")?;
+            self.output
+                .write_all(self.options.indent.repeat(depth).as_bytes())?;
+        }
+        if let Some(key) = key {
+            let mut text = serde_json::to_string(key)?;
+            if self.options.ascii {
+                text = escape_non_ascii(&text);
+            }
+            self.output.write_all(text.as_bytes())?;
+            self.output
+                .write_all(if self.options.compact { b":" } else { b": " })?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let frame = self.frames.pop().context("Unbalanced JSON document")?;
+        if !frame.open {
+            // Nothing was written inside: the container is empty, or `null`
+            let v = match (frame.or_null, frame.object) {
+                (true, _) => Value::Null,
+                (false, true) => Value::Object(serde_json::Map::new()),
+                (false, false) => Value::Array(Vec::new()),
+            };
+            if keeps(frame.filter, &v) {
+                self.write_value(frame.key.as_deref(), &v)?;
+            }
+            return Ok(());
+        }
+        if frame.written > 0 && !self.options.compact {
+            self.output.write_all(b"\n")?;
+            self.output
+                .write_all(self.options.indent.repeat(self.frames.len()).as_bytes())?;
+        }
+        let bracket = if frame.object { b"}" } else { b"]" };
+        self.output.write_all(bracket)?;
+        Ok(())
+    }
+}
+
+/// Whether a container keeps the result `v` of a child.
+fn keeps(filter: Filter, v: &Value) -> bool {
+    match (filter, v) {
+        (Filter::All, _) => true,
+        (_, Value::Null) => false,
+        (Filter::NotEmptyOrNull, Value::Array(array)) => !array.is_empty(),
+        (Filter::NotEmptyOrNull | Filter::NotEmptyObjectOrNull, Value::Object(map)) => {
+            !map.is_empty()
+        }
+        _ => true,
+    }
+}
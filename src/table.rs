@@ -0,0 +1,103 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+
+/// Writes an Array of Objects as a CSV (or TSV, with `b'\t'`) table: a header row with the keys,
+/// then a row per Object. The columns are the keys in the order they first appear, unless
+/// `columns` lists them. Nested Objects and Arrays are written as JSON text.
+pub fn to_csv(v: &Value, delimiter: u8, columns: &[String]) -> Result<String> {
+    let rows = find_rows(v)?;
+    let columns = if columns.is_empty() {
+        let mut keys: Vec<String> = Vec::new();
+        for row in &rows {
+            for k in row.keys() {
+                if !keys.contains(k) {
+                    keys.push(k.clone());
+                }
+            }
+        }
+        keys
+    } else {
+        columns.to_vec()
+    };
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+    writer.write_record(&columns)?;
+    for row in rows {
+        let mut record = Vec::new();
+        for column in &columns {
+            record.push(match row.get(column) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(s)) => s.clone(),
+                Some(other) => serde_json::to_string(other)?,
+            });
+        }
+        writer.write_record(&record)?;
+    }
+    String::from_utf8(writer.into_inner()?).context("Could not write CSV")
+}
+
+/// Reads a CSV (or TSV) table with a header row as an Array of Objects, one per row, with the
+/// header as keys. The cells are Strings, unless `infer_types`: then the cells that look like
+/// a Number or a Boolean are read as such, and the empty cells as `null`.
+pub fn from_csv(text: &str, delimiter: u8, infer_types: bool) -> Result<Value> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_reader(text.as_bytes());
+    let header = reader.headers().context("Could not parse CSV")?.clone();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.context("Could not parse CSV")?;
+        let row: Map<String, Value> = header
+            .iter()
+            .zip(record.iter())
+            .map(|(k, cell)| (k.to_string(), from_cell(cell, infer_types)))
+            .collect();
+        rows.push(Value::Object(row));
+    }
+    Ok(Value::Array(rows))
+}
+
+fn from_cell(cell: &str, infer_types: bool) -> Value {
+    if !infer_types {
+        return Value::String(cell.to_string());
+    }
+    match cell {
+        "" => Value::Null,
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        // Leading zeros (zip codes, ids) are not valid JSON Numbers, so they stay Strings
+        _ => match serde_json::from_str::<serde_json::Number>(cell) {
+            Ok(n) => Value::Number(n),
+            Err(_) => Value::String(cell.to_string()),
+        },
+    }
+}
+
+/// The rows are the Objects of the first Array found going down the Objects that have a single
+/// key (like what `/data/.0,10 p` leaves). A lone Object is a single row.
+fn find_rows(v: &Value) -> Result<Vec<&Map<String, Value>>> {
+    let mut node = v;
+    loop {
+        match node {
+            Value::Array(vec) => {
+                return vec
+                    .iter()
+                    .enumerate()
+                    .map(|(i, element)| {
+                        element.as_object().with_context(|| {
+                            format!(
+                                "Could not write CSV: element {i} of the Array is not an Object"
+                            )
+                        })
+                    })
+                    .collect();
+            }
+            Value::Object(map) if map.len() == 1 && map.values().all(Value::is_array) => {
+                node = map.values().next().context("Could not write CSV")?;
+            }
+            Value::Object(map) => return Ok(vec![map]),
+            _ => bail!("Could not write CSV: the output must be an Array of Objects"),
+        }
+    }
+}
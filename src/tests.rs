@@ -1,15 +1,25 @@
-use super::grammar::{ArrayRange, RangeType, Rule, SedParser};
+#![allow(clippy::assertions_on_constants)]
+
+use super::commands::count_matches;
+use super::grammar::{compile_program, parse_program, ArrayRange, RangeType, Rule, SedParser};
 use super::*;
 use pest::Parser;
 use regex::Regex;
 
+/// Parses a single jed command, e.g. `/author/ s/old/new/g`.
+fn parse_grammar(input: &String) -> anyhow::Result<(Vec<RangeType>, JedCommand)> {
+    let mut program = parse_program(input)?;
+    anyhow::ensure!(program.len() == 1, "Expected a single jed command: {input}");
+    Ok(program.remove(0))
+}
+
 #[test]
 fn test_substitute_keys_1() {
     let some_json = r#"
     {"sha": "0eb3da11ed489189963045a3d4eb21ba343736cb", "node_id": "C_kwDOAE3WVdoAKDBlYjNkYTExZWQ0ODkxODk5NjMwNDVhM2Q0ZWIyMWJhMzQzNzM2Y2I"}"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let replace_regex = Regex::new("sha").unwrap();
-    v = substitute_keys(v, &replace_regex, &String::from("new_sha"));
+    (v, _) = substitute_keys(v, &replace_regex, &String::from("new_sha"));
     assert_eq!(v["new_sha"], "0eb3da11ed489189963045a3d4eb21ba343736cb");
 }
 
@@ -25,7 +35,7 @@ fn test_substitute_keys_recursivity() {
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let replace_regex = Regex::new("a").unwrap();
-    v = substitute_keys(v, &replace_regex, &String::from("o"));
+    (v, _) = substitute_keys(v, &replace_regex, &String::from("o"));
     assert_eq!(v["commit"]["outhor"]["nome"], "bigmoonbit");
 }
 #[test]
@@ -41,7 +51,7 @@ fn test_substitute_keys_repeated_keys_keeps_last() {
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let replace_regex = Regex::new("nombre").unwrap();
-    v = substitute_keys(v, &replace_regex, &String::from("name"));
+    (v, _) = substitute_keys(v, &replace_regex, &String::from("name"));
     assert_eq!(v["commit"]["author"]["name"], "hola");
 }
 #[test]
@@ -55,7 +65,7 @@ fn test_substitute_keys_recursivity_inside_lists() {
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let replace_regex = Regex::new("author").unwrap();
-    v = substitute_keys(v, &replace_regex, &String::from("autor"));
+    (v, _) = substitute_keys(v, &replace_regex, &String::from("autor"));
     assert_eq!(v["commit"][0]["autor"], "camilo");
     assert_eq!(v["commit"][1]["autor"], "andres");
 }
@@ -80,7 +90,7 @@ fn test_substitute_keys_with_filters() {
         RangeType::Key(Regex::new("author").unwrap()),
     ];
     let replace_regex = Regex::new("name").unwrap();
-    v = substitute_keys_on_specified_ranges(v, &stack, &replace_regex, &String::from("nom"));
+    (v, _) = substitute_keys_on_specified_ranges(v, &stack, &replace_regex, &String::from("nom"));
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["commit"]["author"]["nom"], "camilo");
     assert_eq!(v["root"]["commit"]["contributor"]["name"], "camilo");
@@ -103,7 +113,7 @@ fn test_substitute_keys_with_filters_2() {
         RangeType::Array(ArrayRange { begin: 0, end: 0 }),
     ];
     let replace_regex = Regex::new("author").unwrap();
-    v = substitute_keys_on_specified_ranges(v, &stack, &replace_regex, &String::from("nom"));
+    (v, _) = substitute_keys_on_specified_ranges(v, &stack, &replace_regex, &String::from("nom"));
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["commit"][0]["nom"], "camilo");
     assert_eq!(v["root"]["commit"][1]["author"], "andres");
@@ -119,7 +129,7 @@ fn test_substitute_values() {
     }
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
-    v = substitute_values(v, &Regex::new("oo").unwrap(), &String::from("AAA"));
+    (v, _) = substitute_values(v, &Regex::new("oo").unwrap(), &String::from("AAA"));
     assert_eq!(v["commit"]["author"]["name"], "bigmAAAnbit");
 }
 #[test]
@@ -133,7 +143,7 @@ fn test_substitute_values_2() {
     }
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
-    v = substitute_values(v, &Regex::new("o").unwrap(), &String::from("A"));
+    (v, _) = substitute_values(v, &Regex::new("o").unwrap(), &String::from("A"));
     assert_eq!(v["commit"]["author"]["name"], "bigmAAnbit");
 }
 #[test]
@@ -146,7 +156,7 @@ fn test_substitute_values_recursivity_inside_lists() {
         ]
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
-    v = substitute_values(v, &Regex::new("andres").unwrap(), &String::from("mata"));
+    (v, _) = substitute_values(v, &Regex::new("andres").unwrap(), &String::from("mata"));
     assert_eq!(v["commit"][1]["author"], "mata");
 }
 #[test]
@@ -158,7 +168,7 @@ fn test_substitute_values_recursivity_with_list_in_the_root() {
     ]
     "#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
-    v = substitute_values(v, &Regex::new("andres").unwrap(), &String::from("mata"));
+    (v, _) = substitute_values(v, &Regex::new("andres").unwrap(), &String::from("mata"));
     assert_eq!(v[1]["author"], "mata");
 }
 #[test]
@@ -172,7 +182,7 @@ fn test_substitute_values_numbers_can_be_replaced() {
     }
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
-    v = substitute_values(v, &Regex::new("5").unwrap(), &String::from("6"));
+    (v, _) = substitute_values(v, &Regex::new("5").unwrap(), &String::from("6"));
     assert_eq!(v["commit"]["author"]["name"], 6);
 }
 #[test]
@@ -186,7 +196,7 @@ fn test_substitute_values_booleans_can_be_modified() {
     }
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
-    v = substitute_values(v, &Regex::new("true").unwrap(), &String::from("false"));
+    (v, _) = substitute_values(v, &Regex::new("true").unwrap(), &String::from("false"));
     assert_eq!(v["commit"]["author"]["name"], false);
 }
 #[test]
@@ -196,7 +206,7 @@ fn test_substitute_values_random_bug() {
     "sha": "03cb1e19da91f0df728914d4c8717f7490df04e4"
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
-    v = substitute_values(v, &Regex::new(".+").unwrap(), &String::from("hola"));
+    (v, _) = substitute_values(v, &Regex::new(".+").unwrap(), &String::from("hola"));
     assert_eq!(v["sha"], "hola");
 }
 #[test]
@@ -206,7 +216,7 @@ fn test_substitute_values_numbers_can_be_replaced_2() {
     "sha": 0
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
-    v = substitute_values(v, &Regex::new(".+").unwrap(), &String::from("hola"));
+    (v, _) = substitute_values(v, &Regex::new(".+").unwrap(), &String::from("hola"));
     assert_eq!(v["sha"], "hola");
 }
 #[test]
@@ -216,7 +226,7 @@ fn test_substitute_values_nulls_can_be_replaced() {
     "sha": null
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
-    v = substitute_values(v, &Regex::new(".+").unwrap(), &String::from("hola"));
+    (v, _) = substitute_values(v, &Regex::new(".+").unwrap(), &String::from("hola"));
     assert_eq!(v["sha"], "hola");
 }
 #[test]
@@ -226,7 +236,7 @@ fn test_substitute_values_new_lines_can_be_replaced() {
     "sha": "a\\nb"
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
-    v = substitute_values(v, &Regex::new(".+").unwrap(), &String::from("hola"));
+    (v, _) = substitute_values(v, &Regex::new(".+").unwrap(), &String::from("hola"));
     assert_eq!(v["sha"], "hola");
 }
 #[test]
@@ -409,7 +419,7 @@ fn test_filter_substitute_1() {
     ];
     let old_regex = Regex::new("oo").unwrap();
     let new_regex = String::from("AA");
-    v = substitute_values_on_specified_ranges(v, &stack, &old_regex, &new_regex);
+    (v, _) = substitute_values_on_specified_ranges(v, &stack, &old_regex, &new_regex);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["commit"]["author"]["name"], "bigmAAnbit");
     assert_eq!(v["root"]["commit"]["author"]["nombre"], "hoola");
@@ -433,7 +443,7 @@ fn test_filter_substitute_with_arrays() {
     let stack = vec![RangeType::Key(Regex::new("commit").unwrap())];
     let old_regex = Regex::new("a").unwrap();
     let new_regex = String::from("x");
-    v = substitute_values_on_specified_ranges(v, &stack, &old_regex, &new_regex);
+    (v, _) = substitute_values_on_specified_ranges(v, &stack, &old_regex, &new_regex);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["commit"][0]["name"], "cxmilo");
     assert_eq!(v["root"]["commit"][1]["name"], "xndres");
@@ -460,7 +470,7 @@ fn test_filter_substitute_with_arrays_and_ranges() {
     ];
     let search_regex = Regex::new("a").unwrap();
     let replace_with = String::from("x");
-    v = substitute_values_on_specified_ranges(v, &stack, &search_regex, &replace_with);
+    (v, _) = substitute_values_on_specified_ranges(v, &stack, &search_regex, &replace_with);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["commit"][0]["name"], "cxmilo");
     assert_eq!(v["root"]["commit"][1]["name"], "andres");
@@ -487,7 +497,7 @@ fn test_filter_substitute_with_arrays_and_ranges_2() {
     ];
     let search_regex = Regex::new("a").unwrap();
     let replace_with = String::from("x");
-    v = substitute_values_on_specified_ranges(v, &stack, &search_regex, &replace_with);
+    (v, _) = substitute_values_on_specified_ranges(v, &stack, &search_regex, &replace_with);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["commit"][0]["name"], Value::Null);
     assert_eq!(v["commit"][1]["name"], Value::Null);
@@ -516,7 +526,7 @@ fn test_filter_substitute_with_arrays_and_ranges_3() {
     )];
     let search_regex = Regex::new("Bank|webauth").unwrap();
     let replace_with = String::from("PERRO");
-    v = substitute_values_on_specified_ranges(v, &stack, &search_regex, &replace_with);
+    (v, _) = substitute_values_on_specified_ranges(v, &stack, &search_regex, &replace_with);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["connectors"][0]["name"], "Aachener PERRO eG");
     assert_eq!(
@@ -531,7 +541,7 @@ fn test_filter_substitute_with_arrays_and_ranges_3() {
     ];
     let search_regex = Regex::new("Bank|webauth").unwrap();
     let replace_with = String::from("PERRO");
-    v = substitute_values_on_specified_ranges(v, &stack, &search_regex, &replace_with);
+    (v, _) = substitute_values_on_specified_ranges(v, &stack, &search_regex, &replace_with);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["connectors"][0]["name"], "Aachener PERRO eG");
     assert_eq!(
@@ -562,7 +572,7 @@ fn test_filter_substitute_with_arrays_and_ranges_3() {
     ];
     let search_regex = Regex::new("Bank|webauth").unwrap();
     let replace_with = String::from("PERRO");
-    v = substitute_values_on_specified_ranges(v, &stack, &search_regex, &replace_with);
+    (v, _) = substitute_values_on_specified_ranges(v, &stack, &search_regex, &replace_with);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["connectors"]["name"], "Aachener PERRO eG");
     assert_eq!(
@@ -589,7 +599,7 @@ fn test_substitute_value_ranges() {
     let stack = vec![RangeType::Value(Regex::new("spanish").unwrap())];
     let search_regex = Regex::new("credentials").unwrap();
     let replace_with = String::from("credenciales");
-    v = substitute_values_on_specified_ranges(v, &stack, &search_regex, &replace_with);
+    (v, _) = substitute_values_on_specified_ranges(v, &stack, &search_regex, &replace_with);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["connectors"][0]["auth_mechanism"], "credenciales_spanish");
     assert_eq!(
@@ -630,13 +640,13 @@ fn test_print_1() {
         RangeType::Key(Regex::new("commit").unwrap()),
         RangeType::Array(ArrayRange { begin: 0, end: 0 }),
     ];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["commit"][0]["name"], "camilo");
     assert_eq!(v["root"]["commit"][1]["name"], Value::Null);
 
     let stack = vec![RangeType::Key(Regex::new("doesnt-exists").unwrap())];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["commit"][0]["name"], Value::Null);
     assert_eq!(v["root"]["commit"][1]["name"], Value::Null);
@@ -662,7 +672,7 @@ fn test_print_2() {
         RangeType::Array(ArrayRange { begin: 0, end: 0 }),
         RangeType::Key(Regex::new(".*type.*").unwrap()),
     ];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["connectors"][0]["account_types"][0], "checking");
     assert_eq!(v["root"]["connectors"][0]["account_usages"], Value::Null);
@@ -681,7 +691,7 @@ fn test_print_1_flexible() {
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let stack = vec![RangeType::Key(Regex::new("account_types").unwrap())];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["connectors"]["account_types"][0], "checking");
     assert_eq!(v["connectors"]["account_usages"], Value::Null);
@@ -705,7 +715,7 @@ fn test_print_2_flexible() {
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let stack = vec![RangeType::Key(Regex::new("account_types").unwrap())];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["connectors"][0]["account_types"][0], "checking");
     match v["connectors"][0].get("account_usages") {
@@ -730,7 +740,7 @@ fn test_print_3_flexible() {
     let stack = vec![RangeType::Key(
         Regex::new("something that does not exists").unwrap(),
     )];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v, Value::Null);
 }
@@ -756,7 +766,7 @@ fn test_print_4_flexible() {
         RangeType::Key(Regex::new("key1").unwrap()),
         RangeType::Key(Regex::new("key112").unwrap()),
     ];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["key1"]["key112"], "b");
     match v["key1"].get("key11") {
@@ -782,7 +792,7 @@ fn test_print_5_flexible() {
         RangeType::Key(Regex::new("stability").unwrap()),
         RangeType::Key(Regex::new("last_update").unwrap()),
     ];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["connectors"][0]["stability"]["last_update"], "a");
 
@@ -791,7 +801,7 @@ fn test_print_5_flexible() {
         RangeType::Array(ArrayRange { begin: 0, end: 0 }),
         RangeType::Key(Regex::new("stability").unwrap()),
     ];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["connectors"][0]["stability"]["last_update"], "a");
 }
@@ -806,7 +816,7 @@ fn test_print_6_flexible() {
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let stack = vec![RangeType::Array(ArrayRange { begin: 0, end: 0 })];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["connectors"][0], "1");
 }
@@ -833,7 +843,7 @@ fn test_print_7_flexible() {
         RangeType::Array(ArrayRange { begin: 0, end: 1 }),
         RangeType::Key(Regex::new("account").unwrap()),
     ];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     match v["connectors"][1].get("something_that_should_not") {
         Some(_) => assert!(false),
@@ -864,7 +874,7 @@ fn test_print_3() {
         RangeType::Key(Regex::new("^s").unwrap()),
         RangeType::Key(Regex::new("last").unwrap()),
     ];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     match v["root"]["connectors"][0].get("siret") {
         Some(_) => assert!(false),
@@ -879,7 +889,7 @@ fn test_print_4() {
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let stack = vec![];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     match v.get("siret") {
         Some(_) => assert!(true),
@@ -908,7 +918,7 @@ fn test_print_6() {
         RangeType::Key(Regex::new(".*").unwrap()),
         RangeType::Value(Regex::new("^c").unwrap()),
     ];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["commit"][0]["name"], "camilo");
     assert_eq!(v["root"]["commit"][1], Value::Null);
@@ -937,7 +947,7 @@ fn test_print_7() {
         RangeType::Key(Regex::new("name|uuid|auth").unwrap()),
         RangeType::Key(Regex::new("^a").unwrap()),
     ];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     match v["connectors"][0].get("uuid") {
         Some(_) => assert!(false),
@@ -971,7 +981,7 @@ fn test_print_8() {
     "#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let stack = vec![RangeType::Value(Regex::new("credentials").unwrap())];
-    v = print_on_specified_ranges(v, &stack);
+    (v, _) = print_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     match v["connectors"][0].get("uuid") {
         Some(_) => assert!(false),
@@ -1011,14 +1021,14 @@ fn test_delete_1() {
         RangeType::Key(Regex::new("commit").unwrap()),
         RangeType::Array(ArrayRange { begin: 0, end: 0 }),
     ];
-    v = delete_on_specified_ranges(v, &stack);
+    (v, _) = delete_on_specified_ranges(v, &stack);
     println!("Result 1:");
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["commit"][0]["name"], "andres");
 
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let stack = vec![RangeType::Key(Regex::new("doesnt-exists").unwrap())];
-    v = delete_on_specified_ranges(v, &stack);
+    (v, _) = delete_on_specified_ranges(v, &stack);
     println!("Result 2:");
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["root"]["commit"][0]["name"], "camilo");
@@ -1048,7 +1058,7 @@ fn test_delete_3() {
         RangeType::Key(Regex::new("commit").unwrap()),
         RangeType::Key(Regex::new("verification").unwrap()),
     ];
-    v = delete_on_specified_ranges(v, &stack);
+    (v, _) = delete_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     match v["root"]["commit"].get("verification") {
         Some(_) => assert!(false),
@@ -1068,7 +1078,7 @@ fn test_delete_4() {
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let stack = vec![];
-    v = delete_on_specified_ranges(v, &stack);
+    (v, _) = delete_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     match v.get("siret") {
         Some(_) => assert!(false),
@@ -1096,7 +1106,7 @@ fn test_delete_5() {
         RangeType::Array(ArrayRange { begin: 0, end: 0 }),
         RangeType::Key(Regex::new("a").unwrap()),
     ];
-    v = delete_on_specified_ranges(v, &stack);
+    (v, _) = delete_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     match v.get("total") {
         Some(_) => assert!(true),
@@ -1132,7 +1142,7 @@ fn test_delete_6() {
     }"#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let stack = vec![RangeType::Key(Regex::new("^a").unwrap())];
-    v = delete_on_specified_ranges(v, &stack);
+    (v, _) = delete_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     match v["connectors"].get(0) {
         Some(_) => assert!(false),
@@ -1146,7 +1156,7 @@ fn test_delete_7() {
     "#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let stack = vec![RangeType::Key(Regex::new("doesnt exists").unwrap())];
-    v = delete_on_specified_ranges(v, &stack);
+    (v, _) = delete_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v, "connectors");
 }
@@ -1178,7 +1188,7 @@ fn test_delete_8() {
         RangeType::Key(Regex::new("connectors").unwrap()),
         RangeType::Key(Regex::new("doesnt-exists").unwrap()),
     ];
-    v = delete_on_specified_ranges(v, &stack);
+    (v, _) = delete_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     assert_eq!(v["connectors"][0]["capabilities"][0], "bank");
 }
@@ -1198,7 +1208,7 @@ fn test_delete_9() {
     "#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let stack = vec![RangeType::Key(Regex::new("^s").unwrap())];
-    v = delete_on_specified_ranges(v, &stack);
+    (v, _) = delete_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     match v.get("connectors") {
         Some(_) => assert!(true),
@@ -1231,7 +1241,7 @@ fn test_delete_10() {
     "#;
     let mut v: Value = serde_json::from_str(some_json).unwrap();
     let stack = vec![RangeType::Value(Regex::new("credentials").unwrap())];
-    v = delete_on_specified_ranges(v, &stack);
+    (v, _) = delete_on_specified_ranges(v, &stack);
     println!("{}", serde_json::to_string_pretty(&v).unwrap());
     match v["connectors"][0].get("auth_mechanism") {
         Some(_) => assert!(false),
@@ -1246,3 +1256,431 @@ fn test_delete_10() {
         None => assert!(true),
     };
 }
+#[test]
+fn test_grammar_read() {
+    let input = String::from("/config/ r defaults.json");
+    let (stack, command) = parse_grammar(&input).unwrap();
+    match command {
+        JedCommand::Read(params) => {
+            assert_eq!(params.file_name, "defaults.json");
+            assert!(!params.deep);
+        }
+        _ => assert!(false),
+    }
+    assert_eq!(stack.len(), 1);
+    let input = String::from("R /tmp/defaults.json");
+    let (stack, command) = parse_grammar(&input).unwrap();
+    match command {
+        JedCommand::Read(params) => {
+            assert_eq!(params.file_name, "/tmp/defaults.json");
+            assert!(params.deep);
+        }
+        _ => assert!(false),
+    }
+    assert!(stack.is_empty());
+}
+#[test]
+fn test_read_1() {
+    let some_json = r#"
+    {
+      "service": {
+        "name": "api",
+        "limits": { "cpu": 1 }
+      },
+      "other": { "name": "db" }
+    }
+    "#;
+    let defaults = r#"{ "replicas": 2, "limits": { "memory": 512 } }"#;
+    let v: Value = serde_json::from_str(some_json).unwrap();
+    let other: Value = serde_json::from_str(defaults).unwrap();
+    let stack = vec![RangeType::Key(Regex::new("service").unwrap())];
+    let (shallow, _) = read_on_specified_ranges(v.clone(), &stack, &other, false);
+    assert_eq!(shallow["service"]["name"], "api");
+    assert_eq!(shallow["service"]["replicas"], 2);
+    assert_eq!(shallow["service"]["limits"]["memory"], 512);
+    assert!(shallow["service"]["limits"].get("cpu").is_none());
+    assert!(shallow["other"].get("replicas").is_none());
+    let (deep, _) = read_on_specified_ranges(v, &stack, &other, true);
+    assert_eq!(deep["service"]["limits"]["cpu"], 1);
+    assert_eq!(deep["service"]["limits"]["memory"], 512);
+}
+#[test]
+fn test_read_2() {
+    let some_json = r#"
+    {
+      "hosts": [ "a", "b" ],
+      "users": [ { "name": "camilo" }, { "name": "andres" } ]
+    }
+    "#;
+    let v: Value = serde_json::from_str(some_json).unwrap();
+    let stack = vec![RangeType::Key(Regex::new("hosts").unwrap())];
+    let other: Value = serde_json::from_str(r#"[ "c" ]"#).unwrap();
+    let (v, _) = read_on_specified_ranges(v, &stack, &other, false);
+    assert_eq!(v["hosts"][2], "c");
+    let stack = vec![
+        RangeType::Key(Regex::new("users").unwrap()),
+        RangeType::Array(ArrayRange { begin: 1, end: 1 }),
+    ];
+    let other: Value = serde_json::from_str(r#"{ "admin": true }"#).unwrap();
+    let (v, _) = read_on_specified_ranges(v, &stack, &other, false);
+    assert!(v["users"][0].get("admin").is_none());
+    assert_eq!(v["users"][1]["admin"], true);
+    assert_eq!(v["users"][1]["name"], "andres");
+}
+#[test]
+fn test_read_file_once_at_compile() {
+    let file = std::env::temp_dir().join(format!("jed-read-once-{}.json", std::process::id()));
+    std::fs::write(&file, r#"{"replicas": 2}"#).unwrap();
+    let program = Program::compile(&format!("r {}", file.display())).unwrap();
+    std::fs::write(&file, r#"{"replicas": 3}"#).unwrap();
+    let v = program.apply(serde_json::json!({})).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(v["replicas"], 2);
+}
+#[test]
+fn test_grammar_program() {
+    let input = String::from("/^id$/ h; /meta/./name/ s/a/b/g;;x");
+    let program = parse_program(&input).unwrap();
+    assert_eq!(program.len(), 3);
+    match &program[0] {
+        (stack, JedCommand::Hold) => assert_eq!(stack.len(), 1),
+        _ => assert!(false),
+    }
+    match &program[1] {
+        (stack, JedCommand::Substitute(params)) => {
+            assert_eq!(stack.len(), 2);
+            assert_eq!(params.pattern.as_str(), "a");
+            assert_eq!(params.replacement, "b");
+        }
+        _ => assert!(false),
+    }
+    match &program[2] {
+        (stack, JedCommand::Exchange) => assert!(stack.is_empty()),
+        _ => assert!(false),
+    }
+    assert!(parse_program(&String::from("p d")).is_err());
+}
+#[test]
+fn test_grammar_substitute_delimiters_and_flags() {
+    for input in ["s/a/b", "s/a/b;", "s/a/b/", "s/a;b/c/g; p"] {
+        let program = parse_program(&String::from(input)).unwrap();
+        assert!(
+            matches!(&program[0], (_, JedCommand::Substitute(_))),
+            "{input}"
+        );
+    }
+    match parse_program(&String::from("s/a;b/c;d/"))
+        .unwrap()
+        .remove(0)
+    {
+        (_, JedCommand::Substitute(params)) => {
+            assert_eq!(params.pattern.as_str(), "a;b");
+            assert_eq!(params.replacement, "c;d");
+        }
+        _ => assert!(false),
+    }
+    // Without its last `/`, a substitution doesn't take the commands after it
+    for input in ["s/x/y; p", "S/x/y;p", "s/x; p", "s/a/b/i", "s/a/b/xyz"] {
+        assert!(parse_program(&String::from(input)).is_err(), "{input}");
+    }
+}
+#[test]
+fn test_hold_1() {
+    let some_json = r#"
+    {
+      "id": 7,
+      "name": "api",
+      "meta": { "owner": "camilo" }
+    }
+    "#;
+    let v: Value = serde_json::from_str(some_json).unwrap();
+    let mut hold = HoldSpace::default();
+    let stack = vec![RangeType::Key(Regex::new("^(id|name)$").unwrap())];
+    let (v, _) = hold_on_specified_ranges(v, &stack, &mut hold, false);
+    assert_eq!(hold.value, serde_json::json!([7, "api"]));
+    assert_eq!(v["id"], 7);
+    let stack = vec![RangeType::Key(Regex::new("meta").unwrap())];
+    let (v, _) = get_on_specified_ranges(v, &stack, &hold, true);
+    assert_eq!(v["meta"]["owner"], "camilo");
+    assert_eq!(v["meta"]["id"], 7);
+    assert_eq!(v["meta"]["name"], "api");
+    let (v, _) = get_on_specified_ranges(v, &stack, &hold, false);
+    assert_eq!(v["meta"], serde_json::json!([7, "api"]));
+}
+#[test]
+fn test_hold_2() {
+    let some_json = r#"
+    {
+      "tags": [ "a", "b", "c" ]
+    }
+    "#;
+    let v: Value = serde_json::from_str(some_json).unwrap();
+    let mut hold = HoldSpace::default();
+    let first = vec![
+        RangeType::Key(Regex::new("tags").unwrap()),
+        RangeType::Array(ArrayRange { begin: 0, end: 0 }),
+    ];
+    let last = vec![
+        RangeType::Key(Regex::new("tags").unwrap()),
+        RangeType::Array(ArrayRange { begin: 2, end: 2 }),
+    ];
+    let (v, _) = hold_on_specified_ranges(v, &first, &mut hold, false);
+    assert_eq!(hold.value, "a");
+    let (v, _) = hold_on_specified_ranges(v, &last, &mut hold, true);
+    assert_eq!(hold.value, serde_json::json!(["a", "c"]));
+    let (v, _) = exchange_on_specified_ranges(v, &last, &mut hold);
+    assert_eq!(hold.value, "c");
+    assert_eq!(v["tags"][2], serde_json::json!(["a", "c"]));
+    let (v, _) = exchange_on_specified_ranges(v, &first, &mut hold);
+    assert_eq!(hold.value, "a");
+    assert_eq!(v["tags"][0], "c");
+}
+#[test]
+fn test_compile_program() {
+    let inputs = vec![String::from(":loop; s/a/b/; t loop"), String::from("b")];
+    let program = compile_program(&inputs).unwrap();
+    assert_eq!(program.commands.len(), 4);
+    match &program.commands[2].1 {
+        JedCommand::BranchIfSubstituted(params) => {
+            assert_eq!(params.label.as_deref(), Some("loop"));
+            assert_eq!(params.target, 0);
+        }
+        _ => assert!(false),
+    }
+    match &program.commands[3].1 {
+        JedCommand::Branch(params) => {
+            assert!(params.label.is_none());
+            assert_eq!(params.target, 4);
+        }
+        _ => assert!(false),
+    }
+    assert!(compile_program(&[String::from("b missing")]).is_err());
+    assert!(compile_program(&[String::from(":a; :a")]).is_err());
+}
+#[test]
+fn test_substitute_reports_replacements() {
+    let some_json = r#"
+    {
+      "commit": [
+        { "author": "camilo", "date": 2026 },
+        { "author": "andres", "date": null }
+      ]
+    }"#;
+    let v: Value = serde_json::from_str(some_json).unwrap();
+    let (_, replaced) = substitute_values(v.clone(), &Regex::new("a").unwrap(), &String::from("A"));
+    assert_eq!(replaced, 2);
+    let (_, replaced) = substitute_values(v.clone(), &Regex::new("z").unwrap(), &String::from("A"));
+    assert_eq!(replaced, 0);
+    let (_, replaced) = substitute_keys(v.clone(), &Regex::new("^a").unwrap(), &String::from("A"));
+    assert_eq!(replaced, 2);
+    let stack = vec![RangeType::Array(ArrayRange { begin: 1, end: 1 })];
+    let (v, replaced) =
+        substitute_values_on_specified_ranges(v, &stack, &Regex::new("a").unwrap(), "A");
+    assert_eq!(replaced, 1);
+    assert_eq!(v["commit"][0]["author"], "camilo");
+    assert_eq!(v["commit"][1]["author"], "Andres");
+}
+#[test]
+fn test_run_program_branches() {
+    let v: Value = serde_json::from_str(r#"{ "version": "1.2.3" }"#).unwrap();
+    let program = compile_program(&[String::from(r":loop; s/\./-/; t loop")]).unwrap();
+    let output = run_program(&program, v).unwrap();
+    assert_eq!(output.v["version"], "1-2-3");
+    let program = compile_program(&[String::from("s/9/0/; T end; s/1/X/; :end")]).unwrap();
+    let output = run_program(&program, output.v).unwrap();
+    assert_eq!(output.v["version"], "1-2-3");
+}
+#[test]
+fn test_run_program_loops_until_the_address_stops_matching() {
+    let v = serde_json::json!({"a": "xxxy", "b": ["xy", "y"]});
+    let program = compile_program(&[String::from(":loop; :/^x/ s/^x//; :/^x/ b loop")]).unwrap();
+    let output = run_program(&program, v).unwrap();
+    assert_eq!(output.v, serde_json::json!({"a": "y", "b": ["y", "y"]}));
+    assert_eq!(output.matches, 4);
+    let v = serde_json::json!({"n": "xx", "done": false});
+    let program = compile_program(&[String::from(
+        ":loop; /done/ b end; s/^x//; T finish; b loop; :finish; S/n/m/; :end",
+    )])
+    .unwrap();
+    let output = run_program(&program, v).unwrap();
+    assert_eq!(output.v, serde_json::json!({"n": "xx", "done": false}));
+    let v = serde_json::json!({"n": "xx"});
+    let output = run_program(&program, v).unwrap();
+    assert_eq!(output.v, serde_json::json!({"m": ""}));
+}
+#[test]
+fn test_matches_any() {
+    let v: Value = serde_json::from_str(r#"{"a": [{"b": 1}, {"b": 2}], "c": {"b": 3}}"#).unwrap();
+    let (stack, _) = parse_grammar(&String::from("/b/ p")).unwrap();
+    assert_eq!(count_matches(&v, &stack), 3);
+    assert!(matches_any(&v, &stack));
+    let (stack, _) = parse_grammar(&String::from("/zz/ p")).unwrap();
+    assert!(!matches_any(&v, &stack));
+}
+#[test]
+fn test_exit_code_range() {
+    assert!(compile_program(&[String::from("q 255")]).is_ok());
+    assert!(compile_program(&[String::from("q 256")]).is_err());
+    assert!(compile_program(&[String::from("/a/ Q 300")]).is_err());
+}
+#[test]
+fn test_run_program_quits() {
+    let v: Value = serde_json::from_str(r#"{ "status": { "error": "boom" } }"#).unwrap();
+    let program = compile_program(&[String::from("/error/ q 3; s/boom/bang/")]).unwrap();
+    let output = run_program(&program, v).unwrap();
+    let quit = output.quit.unwrap();
+    assert_eq!(quit.exit_code, 3);
+    assert!(quit.print);
+    assert_eq!(output.v["status"]["error"], "boom");
+    let program = compile_program(&[String::from("/warning/ Q; s/boom/bang/")]).unwrap();
+    let output = run_program(&program, output.v).unwrap();
+    assert!(output.quit.is_none());
+    assert_eq!(output.v["status"]["error"], "bang");
+    let program = compile_program(&[String::from("Q")]).unwrap();
+    let output = run_program(&program, output.v).unwrap();
+    let quit = output.quit.unwrap();
+    assert_eq!(quit.exit_code, 0);
+    assert!(!quit.print);
+}
+#[test]
+fn test_print_without_address() {
+    let v: Value = serde_json::from_str(r#"{"a": [1, null, {}], "b": null}"#).unwrap();
+    let (printed, matches) = print_on_specified_ranges(v.clone(), &[]);
+    assert_eq!(printed, v);
+    assert_eq!(matches, 1);
+}
+#[test]
+fn test_commands_report_matches() {
+    let some_json = r#"
+    {
+      "connectors": [
+        { "name": "bank", "tags": [ "a", "b", "c" ] },
+        { "name": "shop", "tags": [] }
+      ]
+    }"#;
+    let v: Value = serde_json::from_str(some_json).unwrap();
+    let names = vec![RangeType::Key(Regex::new("name").unwrap())];
+    let (_, matches) = print_on_specified_ranges(v.clone(), &names);
+    assert_eq!(matches, 2);
+    let (_, matches) = delete_on_specified_ranges(v.clone(), &names);
+    assert_eq!(matches, 2);
+    let tags = vec![
+        RangeType::Key(Regex::new("tags").unwrap()),
+        RangeType::Array(ArrayRange { begin: 1, end: 5 }),
+    ];
+    let (_, matches) = print_on_specified_ranges(v.clone(), &tags);
+    assert_eq!(matches, 2);
+    let missing = vec![RangeType::Key(Regex::new("missing").unwrap())];
+    let (_, matches) = print_on_specified_ranges(v.clone(), &missing);
+    assert_eq!(matches, 0);
+    assert_eq!(count_matches(&v, &missing), 0);
+    assert_eq!(count_matches(&v, &names), 2);
+    assert_eq!(count_matches(&v, &[]), 1);
+    let (_, matches) = print_on_specified_ranges(v.clone(), &[]);
+    assert_eq!(matches, 1);
+    let program = compile_program(&[String::from("/missing/ p; s/zzz/y/")]).unwrap();
+    assert_eq!(run_program(&program, v.clone()).unwrap().matches, 0);
+    let program = compile_program(&[String::from("s/bank/y/; /missing/ p")]).unwrap();
+    assert_eq!(run_program(&program, v).unwrap().matches, 1);
+}
+#[test]
+fn test_key_order_is_preserved() {
+    let some_json = r#"
+    {
+      "zeta": { "name": "z", "id": 1 },
+      "alpha": { "name": "a", "id": 2 },
+      "mid": 3
+    }"#;
+    let keys = |v: &Value| -> Vec<String> { v.as_object().unwrap().keys().cloned().collect() };
+    let v: Value = serde_json::from_str(some_json).unwrap();
+    assert_eq!(keys(&v), ["zeta", "alpha", "mid"]);
+    let (renamed, _) = substitute_keys(
+        v.clone(),
+        &Regex::new("^alpha$").unwrap(),
+        &String::from("beta"),
+    );
+    assert_eq!(keys(&renamed), ["zeta", "beta", "mid"]);
+    assert_eq!(keys(&renamed["zeta"]), ["name", "id"]);
+    let stack = vec![RangeType::Key(Regex::new("alpha").unwrap())];
+    let (renamed, _) = substitute_keys_on_specified_ranges(
+        v.clone(),
+        &stack,
+        &Regex::new("name").unwrap(),
+        &String::from("label"),
+    );
+    assert_eq!(keys(&renamed["alpha"]), ["label", "id"]);
+    let (deleted, _) = delete_on_specified_ranges(v.clone(), &stack);
+    assert_eq!(keys(&deleted), ["zeta", "mid"]);
+    let stack = vec![RangeType::Key(Regex::new("mid|zeta").unwrap())];
+    let (printed, _) = print_on_specified_ranges(v.clone(), &stack);
+    assert_eq!(keys(&printed), ["zeta", "mid"]);
+    let other: Value = serde_json::from_str(r#"{ "extra": true, "name": "b" }"#).unwrap();
+    let stack = vec![RangeType::Key(Regex::new("alpha").unwrap())];
+    let (merged, _) = read_on_specified_ranges(v, &stack, &other, true);
+    assert_eq!(keys(&merged["alpha"]), ["name", "id", "extra"]);
+    assert_eq!(merged["alpha"]["name"], "b");
+}
+#[test]
+fn test_substitute_values_numbers_are_lossless() {
+    let some_json = r#"
+    {
+      "price": 1.10,
+      "big": 123456789012345678901234567890,
+      "precise": 0.1000000000000000000000000001,
+      "count": 5
+    }"#;
+    let v: Value = serde_json::from_str(some_json).unwrap();
+    let (v, replaced) = substitute_values(v, &Regex::new("^5$").unwrap(), &String::from("6"));
+    assert_eq!(replaced, 1);
+    assert_eq!(v["count"], 6);
+    let text = serde_json::to_string(&v).unwrap();
+    assert!(text.contains("\"price\":1.10"));
+    assert!(text.contains("\"big\":123456789012345678901234567890"));
+    assert!(text.contains("\"precise\":0.1000000000000000000000000001"));
+    let (v, _) = substitute_values(v, &Regex::new("^1234").unwrap(), &String::from("9999"));
+    let text = serde_json::to_string(&v).unwrap();
+    assert!(text.contains("\"big\":999956789012345678901234567890"));
+    let (v, _) = substitute_values(v, &Regex::new(r"^1\.10$").unwrap(), &String::from("2.50"));
+    assert!(serde_json::to_string(&v)
+        .unwrap()
+        .contains("\"price\":2.50"));
+}
+#[test]
+fn test_substitute_values_invalid_numbers_become_strings() {
+    let v: Value = serde_json::from_str(r#"{ "a": 1.5, "b": 2, "c": null }"#).unwrap();
+    let (v, _) = substitute_values(v, &Regex::new(r"^1\.5$").unwrap(), &String::from("NaN"));
+    assert_eq!(v["a"], "NaN");
+    let (v, _) = substitute_values(v, &Regex::new("^2$").unwrap(), &String::from("inf"));
+    assert_eq!(v["b"], "inf");
+    let (v, _) = substitute_values(v, &Regex::new("null").unwrap(), &String::from("007"));
+    assert_eq!(v["c"], "007");
+}
+#[test]
+fn test_count_matches_without_copy() {
+    let v: Value = serde_json::from_str(
+        r#"{"a": {"b": [1, {"a": "x"}], "c": null}, "l": [[{"a": 1}], "a", null], "b": {}}"#,
+    )
+    .unwrap();
+    for address in [
+        "/a/", "/a/./b/", "0,1", "0,0./a/", "/l/.0,2", ":/x/", "/a/:/x/", "/zz/",
+    ] {
+        let (stack, _) = parse_grammar(&format!("{address} p")).unwrap();
+        let (_, matches) =
+            hold_on_specified_ranges(v.clone(), &stack, &mut HoldSpace::default(), false);
+        assert_eq!(count_matches(&v, &stack), matches, "{address}");
+    }
+}
+#[test]
+fn test_hold_bare_values() {
+    let program = Program::compile("/a/ h; /b/ g").unwrap();
+    let v = serde_json::json!({"a": {"x": 1}, "b": 2, "l": [3, 4]});
+    assert_eq!(
+        program.apply(v.clone()).unwrap(),
+        serde_json::json!({"a": {"x": 1}, "b": {"x": 1}, "l": [3, 4]})
+    );
+    let program = Program::compile("/a/ h; /l/.0,0 x; /b/ G").unwrap();
+    assert_eq!(
+        program.apply(v).unwrap(),
+        serde_json::json!({"a": {"x": 1}, "b": 2, "l": [{"x": 1}, 4]})
+    );
+}
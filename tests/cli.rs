@@ -2,8 +2,12 @@ use std::io::Write;
 use std::process::{Command, Stdio};
 
 fn run(expression: &str, input: &str) -> std::process::Output {
+    run_with_args(&["-e", expression], input)
+}
+
+fn run_with_args(args: &[&str], input: impl AsRef<[u8]>) -> std::process::Output {
     let mut child = Command::new(env!("CARGO_BIN_EXE_jed"))
-        .args(["-e", expression])
+        .args(args)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -14,7 +18,7 @@ fn run(expression: &str, input: &str) -> std::process::Output {
         .stdin
         .take()
         .unwrap()
-        .write_all(input.as_bytes())
+        .write_all(input.as_ref())
         .unwrap();
     child.wait_with_output().expect("failed to wait on jed")
 }
@@ -40,4 +44,481 @@ fn delete_key() {
 fn invalid_json_fails() {
     let output = run("s/a/b/", "not json");
     assert!(!output.status.success());
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn read_file_merges_into_matches() {
+    let defaults = std::env::temp_dir().join(format!("jed-defaults-{}.json", std::process::id()));
+    std::fs::write(&defaults, r#"{"replicas": 2}"#).unwrap();
+    let expression = format!("/service/ r {}", defaults.display());
+    let output = run(&expression, r#"{"service": {"name": "api"}}"#);
+    std::fs::remove_file(&defaults).unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["service"]["name"], "api");
+    assert_eq!(json["service"]["replicas"], 2);
+}
+
+#[test]
+fn hold_space_copies_between_keys() {
+    let output = run(
+        "/^id$/ h; /^meta$/ G",
+        r#"{"id": 7, "meta": {"owner": "camilo"}}"#,
+    );
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["id"], 7);
+    assert_eq!(json["meta"]["id"], 7);
+    assert_eq!(json["meta"]["owner"], "camilo");
+}
+
+#[test]
+fn branch_loops_until_no_substitution() {
+    let output = run(r":again; s/\./-/; t again", r#"{"version": "1.2.3"}"#);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["version"], "1-2-3");
+}
+
+#[test]
+fn quit_exits_with_status() {
+    let output = run("/error/ Q 3", r#"{"status": {"error": "boom"}}"#);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    let output = run("/error/ q 3", r#"{"status": {"error": "boom"}}"#);
+    assert_eq!(output.status.code(), Some(3));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["status"]["error"], "boom");
+    let output = run("/error/ Q 3", r#"{"status": "ok"}"#);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn exit_status_reflects_matches() {
+    let output = run("/author/ p", r#"{"name": "camilo"}"#);
+    assert_eq!(output.status.code(), Some(1));
+    let output = run("s/andres/camilo/", r#"{"name": "camilo"}"#);
+    assert_eq!(output.status.code(), Some(1));
+    let output = run("/name/ p", r#"{"name": "camilo"}"#);
+    assert_eq!(output.status.code(), Some(0));
+    let output = run_with_args(&["-c", "-e", "p"], r#"{"name": null}"#);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"name\":null}\n"
+    );
+    let output = run("z", r#"{"name": "camilo"}"#);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn no_match_ok_exits_zero() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jed"))
+        .args(["--no-match-ok", "-e", "/author/ p"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn jed");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(br#"{"name": "camilo"}"#)
+        .unwrap();
+    let output = child.wait_with_output().expect("failed to wait on jed");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn key_order_is_preserved() {
+    let output = run("S/^b$/renamed/", r#"{"c": 1, "b": 2, "a": 3}"#);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let c = stdout.find("\"c\"").unwrap();
+    let renamed = stdout.find("\"renamed\"").unwrap();
+    let a = stdout.find("\"a\"").unwrap();
+    assert!(c < renamed && renamed < a);
+}
+
+#[test]
+fn numbers_are_preserved() {
+    let output = run(
+        "s/camilo/andres/",
+        r#"{"name": "camilo", "price": 1.10, "id": 123456789012345678901234567890}"#,
+    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("1.10"));
+    assert!(stdout.contains("123456789012345678901234567890"));
+}
+
+#[test]
+fn preserve_format_changes_only_the_edited_line() {
+    let input = "{\n    \"name\": \"demo\",\n    \"version\": \"1.0.0\",\n    \"scripts\": {\"build\": \"tsc\",   \"test\": \"jest\"},\n    \"price\": 1.50\n}\n";
+    let output = run_with_args(
+        &["--preserve-format", "-e", "/version/ s/1.0.0/1.1.0/"],
+        input,
+    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, input.replace("1.0.0", "1.1.0"));
+}
+
+#[test]
+fn compact_output() {
+    let output = run_with_args(
+        &["-c", "--sort-keys", "-e", "s/camilo/andres/"],
+        r#"{"name": "camilo", "age": 30}"#,
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"age\":30,\"name\":\"andres\"}\n"
+    );
+}
+
+#[test]
+fn raw_output_prints_strings_unquoted() {
+    let input = r#"{"authors": [{"name": "camilo"}, {"name": "andres"}], "id": 1}"#;
+    let output = run_with_args(&["-r", "-e", "/name/ p"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "camilo\nandres\n"
+    );
+    let output = run_with_args(&["--join", "-e", "/name/ p"], input);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "camiloandres");
+}
+
+#[test]
+fn flatten_and_unflatten() {
+    let output = run_with_args(
+        &["--flatten", "-e", "p"],
+        r#"{"authors": [{"name": "camilo"}], "id": 1}"#,
+    );
+    assert!(output.status.success());
+    let flattened = String::from_utf8(output.stdout).unwrap();
+    assert!(flattened.contains("json.authors[0].name = \"camilo\";\n"));
+    let only_names: String = flattened
+        .lines()
+        .filter(|line| line.contains("name"))
+        .map(|line| format!("{line}\n"))
+        .collect();
+    let output = run_with_args(&["--unflatten", "-c", "-e", "p"], &only_names);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"authors\":[{\"name\":\"camilo\"}]}\n"
+    );
+}
+
+#[test]
+fn emit_patch() {
+    let output = run_with_args(
+        &["--emit-patch", "-c", "-e", "s/camilo/andres/; /id/ d"],
+        r#"{"name": "camilo", "id": 1}"#,
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[{\"op\":\"replace\",\"path\":\"/name\",\"value\":\"andres\"},{\"op\":\"remove\",\"path\":\"/id\"}]\n"
+    );
+}
+
+#[test]
+fn apply_patch_in_place() {
+    let dir = std::env::temp_dir();
+    let file = dir.join(format!("jed-in-place-{}.json", std::process::id()));
+    let json_patch = dir.join(format!("jed-patch-{}.json", std::process::id()));
+    std::fs::write(&file, r#"{"name": "camilo", "id": 1}"#).unwrap();
+    std::fs::write(
+        &json_patch,
+        r#"[{"op": "replace", "path": "/id", "value": 2}]"#,
+    )
+    .unwrap();
+    let output = run_with_args(
+        &[
+            "--apply-patch",
+            json_patch.to_str().unwrap(),
+            "-c",
+            "-i",
+            file.to_str().unwrap(),
+        ],
+        "",
+    );
+    let contents = std::fs::read_to_string(&file).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    std::fs::remove_file(&json_patch).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert_eq!(contents, "{\"name\":\"camilo\",\"id\":2}\n");
+}
+
+#[test]
+fn diff_shows_changes_without_writing() {
+    let file = std::env::temp_dir().join(format!("jed-diff-{}.json", std::process::id()));
+    let input = "{\n  \"name\": \"camilo\",\n  \"id\": 1\n}\n";
+    std::fs::write(&file, input).unwrap();
+    let output = run_with_args(
+        &["--diff", "-i", "-e", "/id/ d", file.to_str().unwrap()],
+        "",
+    );
+    let paths = run_with_args(
+        &["--diff=paths", "-e", "/id/ d", file.to_str().unwrap()],
+        "",
+    );
+    let contents = std::fs::read_to_string(&file).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert!(output.status.success());
+    assert_eq!(contents, input);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("--- a/"));
+    assert!(stdout.contains("\n-  \"id\": 1\n"));
+    assert!(stdout.contains("\n+  \"name\": \"camilo\"\n"));
+    assert_eq!(String::from_utf8(paths.stdout).unwrap(), "- /id: 1\n");
+}
+
+#[test]
+fn yaml_documents() {
+    let input = "name: api\nreplicas: 3\n---\nname: web\n";
+    let output = run_with_args(&["--from", "yaml", "-e", "/name/ s/api/backend/"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "name: backend\nreplicas: 3\n---\nname: web\n"
+    );
+    let output = run_with_args(
+        &["--from", "yaml", "--to", "json", "-c", "-e", "/name/ q"],
+        input,
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"name\":\"api\",\"replicas\":3}\n"
+    );
+}
+
+#[test]
+fn toml_documents() {
+    let input = "[dependencies]\nserde = \"1.0\"\n";
+    let output = run_with_args(
+        &["--from", "toml", "-e", "/dependencies/./serde/ s/1.0/1.1/"],
+        input,
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[dependencies]\nserde = \"1.1\"\n"
+    );
+    let output = run_with_args(&["--to", "toml", "-e", "p"], r#"{"a": null}"#);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("TOML has no null"));
+}
+
+#[test]
+fn jsonc_keeps_comments() {
+    let input = "{\n  // indentation\n  \"tab\": 2, // spaces\n  \"wrap\": true,\n}\n";
+    let output = run_with_args(
+        &["--jsonc", "--preserve-format", "-e", "/tab/ s/2/4/"],
+        input,
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        input.replace("2, // spaces", "4, // spaces")
+    );
+    let output = run_with_args(&["--jsonc", "-c", "-e", "p"], input);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"tab\":2,\"wrap\":true}\n"
+    );
+}
+
+#[test]
+fn to_csv() {
+    let input =
+        r#"{"data": [{"verse": 1, "text": "In the beginning"}, {"verse": 2, "text": "And"}]}"#;
+    let output = run_with_args(&["--to", "csv", "-e", "/data/ p"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "verse,text\n1,In the beginning\n2,And\n"
+    );
+    let output = run_with_args(
+        &["--to", "tsv", "--columns", "text", "-e", "/data/ p"],
+        input,
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "text\nIn the beginning\nAnd\n"
+    );
+}
+
+#[test]
+fn from_csv() {
+    let input = "Customer Id,name\n7,Ana\n";
+    let output = run_with_args(&["--from", "csv", "-e", "S/^Customer Id$/id/"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "id,name\n7,Ana\n"
+    );
+    let output = run_with_args(
+        &[
+            "--from",
+            "csv",
+            "--to",
+            "json",
+            "--infer-types",
+            "-c",
+            "-e",
+            "p",
+        ],
+        input,
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[{\"Customer Id\":7,\"name\":\"Ana\"}]\n"
+    );
+}
+
+#[test]
+fn msgpack_and_cbor() {
+    let input = r#"{"service": "billing", "retries": 3}"#;
+    let output = run_with_args(&["--to", "msgpack", "-e", "s/billing/payments/"], input);
+    assert!(output.status.success());
+    let msgpack = output.stdout;
+    assert_eq!(msgpack[0], 0x82);
+    let output = run_with_args(&["--from", "msgpack", "--to", "cbor", "-e", "p"], &msgpack);
+    assert!(output.status.success());
+    assert_eq!(output.stdout[0], 0xa2);
+    let output = run_with_args(
+        &[
+            "--from",
+            "cbor",
+            "--to",
+            "json",
+            "-c",
+            "-e",
+            "/retries/ s/3/5/",
+        ],
+        &output.stdout,
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"service\":\"payments\",\"retries\":5}\n"
+    );
+    let output = run_with_args(&["--from", "msgpack", "-e", "p"], [0xc1]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = run_with_args(
+        &["--from", "msgpack", "--to", "json", "-c", "-e", "p"],
+        b"\x81\xa1a\xc4\x03abc",
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"a\":\"YWJj\"}\n"
+    );
+    let output = run_with_args(
+        &["--from", "cbor", "--to", "json", "-c", "-e", "p"],
+        b"\xa1\x61a\x43abc",
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"a\":\"YWJj\"}\n"
+    );
+}
+
+#[test]
+fn msgpack_binary_fields_keep_their_type() {
+    let output = run_with_args(
+        &["--from", "msgpack", "-e", "/name/ s/a/b/"],
+        b"\x82\xa4name\xa1a\xa4data\xc4\x03abc",
+    );
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\x82\xa4name\xa1b\xa4data\xc4\x03abc");
+}
+
+#[test]
+fn stream() {
+    let input = r#"{"data": [{"id": 1, "name": "user1"}, {"id": 2, "name": "user2"}]}"#;
+    let output = run_with_args(&["--stream", "-c", "-e", "/name/ s/user/u/"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"data\":[{\"id\":1,\"name\":\"u1\"},{\"id\":2,\"name\":\"u2\"}]}\n"
+    );
+    let output = run_with_args(&["--stream", "-e", "/zz/ d"], input);
+    assert_eq!(output.status.code(), Some(1));
+    let output = run_with_args(&["--stream", "-e", "p; p"], input);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn ndjson_jobs() {
+    let input: String = (0..200)
+        .map(|i| format!("{{\"id\": {i}, \"email\": \"user{i}@example.com\"}}\n\n"))
+        .collect();
+    let output = run_with_args(
+        &["--ndjson", "-j", "4", "-e", "/email/ s/example/test/"],
+        &input,
+    );
+    assert!(output.status.success());
+    let expected: String = (0..200)
+        .map(|i| format!("{{\"id\":{i},\"email\":\"user{i}@test.com\"}}\n"))
+        .collect();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+
+    let output = run_with_args(
+        &["--ndjson", "-j", "4", "-e", "/id/ s/^3$/x/; T; q"],
+        &input,
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 4);
+
+    let output = run_with_args(&["--ndjson", "-j", "2", "-e", "p"], "{}\nnot json\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Could not parse line 2 into JSON"));
+}
+
+#[test]
+fn several_files_in_place() {
+    let dir = std::env::temp_dir();
+    let files: Vec<_> = (0..4)
+        .map(|i| dir.join(format!("jed-files-{}-{i}.json", std::process::id())))
+        .collect();
+    for (i, file) in files.iter().enumerate() {
+        std::fs::write(file, format!("{{\"n\": {i}, \"s\": \"a\"}}")).unwrap();
+    }
+    let names: Vec<&str> = files.iter().map(|file| file.to_str().unwrap()).collect();
+    let printed = run_with_args(
+        &[&["-c", "-j", "3", "-e", "s/a/b/"], &names[..]].concat(),
+        "",
+    );
+    let args = [
+        &["-c", "-i", "-j", "3", "-e", "/n/ s/^2$/x/; T; Q 4"],
+        &names[..],
+    ]
+    .concat();
+    let output = run_with_args(&args, "");
+    let contents: Vec<String> = files
+        .iter()
+        .map(|file| std::fs::read_to_string(file).unwrap())
+        .collect();
+    for file in &files {
+        std::fs::remove_file(file).unwrap();
+    }
+    assert_eq!(
+        String::from_utf8(printed.stdout).unwrap(),
+        "{\"n\":0,\"s\":\"b\"}\n{\"n\":1,\"s\":\"b\"}\n{\"n\":2,\"s\":\"b\"}\n{\"n\":3,\"s\":\"b\"}\n"
+    );
+    // `Q` leaves its file and the ones after it as they were
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(contents[0], "{\"n\":0,\"s\":\"a\"}\n");
+    assert_eq!(contents[2], "{\"n\": 2, \"s\": \"a\"}");
+    assert_eq!(contents[3], "{\"n\": 3, \"s\": \"a\"}");
 }
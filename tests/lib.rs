@@ -0,0 +1,51 @@
+use jed::{ErrorKind, Program};
+use serde_json::json;
+
+#[test]
+fn compile_and_apply() {
+    let program = Program::compile("/author/./url/ s/old/new/; /draft/ d").unwrap();
+    let v = json!({"author": {"url": "old.com", "name": "old"}, "draft": true});
+    assert_eq!(
+        program.apply(v).unwrap(),
+        json!({"author": {"url": "new.com", "name": "old"}})
+    );
+}
+
+#[test]
+fn run_tells_matches_and_quit() {
+    let program = Program::compile_all(&["s/a/b/g", "/stop/ Q 3"]).unwrap();
+    let output = program.run(json!({"x": "aa", "y": "a"})).unwrap();
+    assert_eq!(output.matches, 2);
+    assert!(output.quit.is_none());
+    let output = program.run(json!({"stop": "a"})).unwrap();
+    let quit = output.quit.unwrap();
+    assert_eq!((quit.exit_code, quit.print), (3, false));
+}
+
+#[test]
+fn errors() {
+    let err = Program::compile("/a/ s/(/x/").err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::Compile);
+    assert_eq!(err.to_string(), "Parsing the search pattern failed");
+    assert!(std::error::Error::source(&err).is_some());
+    let err = Program::compile_all(&["b end"]).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::Compile);
+    let err = Program::compile("/a/ z").err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::Compile);
+    assert_eq!(err.to_string(), "Unknown jed command: z");
+
+    let err = Program::compile("r /nonexistent/jed.json").err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::Compile);
+}
+
+#[test]
+fn shared_between_threads() {
+    let program = Program::compile("/n/ s/1/one/").unwrap();
+    let outputs: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..4)
+            .map(|_| scope.spawn(|| program.apply(json!({"n": "1"})).unwrap()))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert!(outputs.iter().all(|v| *v == json!({"n": "one"})));
+}